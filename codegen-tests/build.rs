@@ -0,0 +1,41 @@
+use std::{env, fmt::Write, fs, path::Path};
+
+/// Patterns the generated matchers are checked against the interpreter with.
+const PATTERNS: &[&str] = &[
+    "hello",
+    "^(%a+)=(%d+)$",
+    "(%w+)%s*=%s*(%w+)",
+    "%d+%.?%d*",
+    "[%a_][%w_]*",
+    "[^%s,]+",
+    "a-b",
+    "x?y",
+    "(.-)%1",
+    "%b()",
+    "%f[%a]%a+",
+    "()ab()",
+    "^%s*(.-)%s*$",
+    "[а-я]+ %u",
+    "%x%x?%X",
+    "%p%P%c%C%g%G%l%L",
+    "(%a)%a-%1",
+    "%f[%S]%S*$",
+];
+
+fn main() {
+    let mut source = String::new();
+    let mut table = String::new();
+    for (n, re) in PATTERNS.iter().enumerate() {
+        source.push_str(&rlp::codegen::generate(&format!("matcher_{}", n), re).unwrap());
+        writeln!(table, "    ({:?}, matcher_{}),", re, n).unwrap();
+    }
+    writeln!(
+        source,
+        "pub static MATCHERS: &[(&str, Matcher)] = &[\n{}];",
+        table
+    )
+    .unwrap();
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("matchers.rs");
+    fs::write(out, source).unwrap();
+    println!("cargo:rerun-if-changed=build.rs");
+}
@@ -0,0 +1,5 @@
+//! Matchers generated by `build.rs` with `rlp::codegen`.
+
+pub type Matcher = for<'a> fn(&'a str) -> Option<rlp::Match<'a>>;
+
+include!(concat!(env!("OUT_DIR"), "/matchers.rs"));
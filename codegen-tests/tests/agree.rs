@@ -0,0 +1,32 @@
+use codegen_tests::MATCHERS;
+use rlp::regex::Regex;
+
+const SUBJECTS: &[&str] = &[
+    "",
+    "hello world",
+    "key=42",
+    "  spaced = out  ",
+    "3.14 and 42. and .5",
+    "_private var2 x",
+    "a,b,,c d",
+    "aaab xy y",
+    "abcabc xx",
+    "f(a(b)c) (unclosed",
+    "THE end of words",
+    "ab ab",
+    "привет Мир",
+    "ff0G 1A2z",
+    "!a\tb\u{7f}Z zZ",
+];
+
+#[test]
+fn generated_matchers_agree_with_interpreter() {
+    for (re, matcher) in MATCHERS {
+        let regex = Regex::new(re);
+        for subj in SUBJECTS {
+            let expected = regex.match_one(subj).map(|m| m.captures);
+            let generated = matcher(subj).map(|m| m.captures);
+            assert_eq!(expected, generated, "{:?} on {:?}", re, subj);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
+
+/// Checks a Lua pattern at compile time and expands to a `&'static Regex`
+/// compiled on first use.
+///
+/// ```
+/// let re = rlp_macros::lua_pattern!("(%a+)=(%d+)");
+/// assert!(re.is_match("key=42"));
+/// ```
+///
+/// A malformed pattern is a compile error:
+///
+/// ```compile_fail
+/// let re = rlp_macros::lua_pattern!("(");
+/// ```
+#[proc_macro]
+pub fn lua_pattern(input: TokenStream) -> TokenStream {
+    let literal = match single_literal(input) {
+        Ok(literal) => literal,
+        Err((message, span)) => return compile_error(&message, span),
+    };
+    let pattern = match unquote(&literal.to_string()) {
+        Some(pattern) => pattern,
+        None => return compile_error("Expected a string literal.", literal.span()),
+    };
+    if let Err(e) = rlp::regex::try_compile(&pattern) {
+        return compile_error(&format!("Malformed Lua pattern: {}", e), literal.span());
+    }
+    lazy_static_regex(literal)
+}
+
+fn single_literal(input: TokenStream) -> Result<Literal, (String, Span)> {
+    let mut tokens = input.into_iter();
+    let literal = match tokens.next() {
+        Some(TokenTree::Literal(literal)) => literal,
+        // Literals forwarded by `macro_rules!` come wrapped in an invisible group.
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::None => {
+            return single_literal(group.stream())
+        }
+        Some(other) => return Err(("Expected a string literal.".into(), other.span())),
+        None => return Err(("Expected a string literal.".into(), Span::call_site())),
+    };
+    match tokens.next() {
+        None => Ok(literal),
+        Some(TokenTree::Punct(p)) if p.as_char() == ',' && tokens.next().is_none() => Ok(literal),
+        Some(other) => Err(("Unexpected token after the pattern.".into(), other.span())),
+    }
+}
+
+/// `{ static RE: OnceLock<Regex> = OnceLock::new(); RE.get_or_init(|| Regex::new(literal)) }`
+fn lazy_static_regex(literal: Literal) -> TokenStream {
+    let span = literal.span();
+    let path = |segments: &[&str]| -> Vec<TokenTree> {
+        segments
+            .iter()
+            .flat_map(|segment| {
+                [
+                    TokenTree::Punct(Punct::new(':', Spacing::Joint)),
+                    TokenTree::Punct(Punct::new(':', Spacing::Alone)),
+                    TokenTree::Ident(Ident::new(segment, span)),
+                ]
+            })
+            .collect()
+    };
+    let once_lock = path(&["std", "sync", "OnceLock"]);
+    let regex = path(&["rlp", "regex", "Regex"]);
+    let ident = |name: &str| TokenTree::Ident(Ident::new(name, span));
+    let punct = |c: char| TokenTree::Punct(Punct::new(c, Spacing::Alone));
+    let group = |delimiter, tokens: Vec<TokenTree>| {
+        TokenTree::Group(Group::new(delimiter, tokens.into_iter().collect()))
+    };
+
+    let mut body = vec![ident("static"), ident("RE"), punct(':')];
+    body.extend(once_lock.clone());
+    body.push(punct('<'));
+    body.extend(regex.clone());
+    body.push(punct('>'));
+    body.push(punct('='));
+    body.extend(once_lock);
+    body.extend(path(&["new"]));
+    body.push(group(Delimiter::Parenthesis, vec![]));
+    body.push(punct(';'));
+    body.extend([ident("RE"), punct('.'), ident("get_or_init")]);
+    let mut init = vec![
+        TokenTree::Punct(Punct::new('|', Spacing::Joint)),
+        punct('|'),
+    ];
+    init.extend(regex);
+    init.extend(path(&["new"]));
+    init.push(group(
+        Delimiter::Parenthesis,
+        vec![TokenTree::Literal(literal)],
+    ));
+    body.push(group(Delimiter::Parenthesis, init));
+    [group(Delimiter::Brace, body)].into_iter().collect()
+}
+
+fn compile_error(message: &str, span: Span) -> TokenStream {
+    let mut literal = Literal::string(message);
+    literal.set_span(span);
+    let mut tokens = [
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(Punct::new('!', Spacing::Alone)),
+        TokenTree::Group(Group::new(
+            Delimiter::Parenthesis,
+            [TokenTree::Literal(literal)].into_iter().collect(),
+        )),
+    ];
+    for token in &mut tokens {
+        token.set_span(span);
+    }
+    tokens.into_iter().collect()
+}
+
+/// Value of a string literal as written in the source, `None` for anything else.
+fn unquote(source: &str) -> Option<String> {
+    if let Some(raw) = source.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        let raw = raw.get(hashes..raw.len() - hashes)?;
+        return Some(raw.strip_prefix('"')?.strip_suffix('"')?.to_owned());
+    }
+    let quoted = source.strip_prefix('"')?.strip_suffix('"')?;
+    let mut value = String::with_capacity(quoted.len());
+    let mut chars = quoted.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => value.push('\n'),
+            'r' => value.push('\r'),
+            't' => value.push('\t'),
+            '0' => value.push('\0'),
+            c @ ('\\' | '\'' | '"') => value.push(c),
+            'x' => {
+                let hex = [chars.next()?, chars.next()?].iter().collect::<String>();
+                value.push(u8::from_str_radix(&hex, 16).ok().filter(u8::is_ascii)? as char);
+            }
+            'u' => {
+                chars.next().filter(|c| *c == '{')?;
+                let hex = chars
+                    .by_ref()
+                    .take_while(|c| *c != '}')
+                    .filter(|c| *c != '_')
+                    .collect::<String>();
+                value.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+            }
+            '\n' => while chars.next_if(|c| c.is_whitespace()).is_some() {},
+            _ => return None,
+        }
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::unquote;
+
+    #[test]
+    fn unquotes_string_literals() {
+        let cases = [
+            (r#""%d+""#, Some("%d+")),
+            (r#""a\tb\\n\"""#, Some("a\tb\\n\"")),
+            (r#""\x41\u{416}\u{1_F600}""#, Some("AЖ😀")),
+            ("\"one \\\n    two\"", Some("one two")),
+            (r##"r"%s*(\w)""##, Some(r"%s*(\w)")),
+            (r###"r#"say "hi""#"###, Some(r#"say "hi""#)),
+            (r#"b"bytes""#, None),
+            ("42", None),
+            (r#""\q""#, None),
+        ];
+        for (source, value) in cases {
+            assert_eq!(value.map(str::to_owned), unquote(source), "{}", source);
+        }
+    }
+}
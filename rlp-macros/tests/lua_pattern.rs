@@ -0,0 +1,26 @@
+use rlp::regex::Regex;
+use rlp_macros::lua_pattern;
+
+fn key_value() -> &'static Regex {
+    lua_pattern!("(%a+)=(%d+)")
+}
+
+#[test]
+fn expands_to_static_regex() {
+    let re = key_value();
+    assert!(std::ptr::eq(re, key_value()));
+    let m = re.match_one("x key=42").unwrap();
+    assert_eq!([2..8, 2..5, 6..8].as_slice(), m.captures.as_ref());
+    assert_eq!(Some(0..3), lua_pattern!(r"%d+").find("123abc"));
+    assert!(lua_pattern!("^\u{416}+$",).is_match("ЖЖЖ"));
+}
+
+#[test]
+fn accepts_literals_from_declarative_macros() {
+    macro_rules! words {
+        ($re:literal) => {
+            lua_pattern!($re)
+        };
+    }
+    assert_eq!(Some(1..4), words!("%a+").find(" abc "));
+}
@@ -0,0 +1,207 @@
+use std::sync::OnceLock;
+
+use super::character_class::CharacterClass;
+
+const NON_ASCII: (u32, u32) = (0x80, char::MAX as u32);
+
+type Ranges = Box<[(u32, u32)]>;
+
+/// Flat form of a [`CharacterClass`]: a bitmap for ASCII and a sorted table
+/// of disjoint ranges for everything above it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClassTable {
+    ascii: [u64; 2],
+    ranges: Ranges,
+}
+
+impl ClassTable {
+    pub fn new(class: &CharacterClass) -> Self {
+        let mut ascii = [0; 2];
+        for c in 0..128u8 {
+            if class.is_matched(c as char) {
+                ascii[c as usize >> 6] |= 1 << (c & 63);
+            }
+        }
+        Self {
+            ascii,
+            ranges: non_ascii_ranges(class).into(),
+        }
+    }
+
+    #[inline]
+    pub fn is_matched(&self, other: char) -> bool {
+        let code = other as u32;
+        if code < 128 {
+            self.ascii[code as usize >> 6] >> (code & 63) & 1 == 1
+        } else {
+            self.ranges
+                .binary_search_by(|&(start, end)| {
+                    if end < code {
+                        std::cmp::Ordering::Less
+                    } else if start > code {
+                        std::cmp::Ordering::Greater
+                    } else {
+                        std::cmp::Ordering::Equal
+                    }
+                })
+                .is_ok()
+        }
+    }
+}
+
+fn non_ascii_ranges(class: &CharacterClass) -> Vec<(u32, u32)> {
+    match class {
+        CharacterClass::Literal(c) => clip(&[(*c as u32, *c as u32)]),
+        CharacterClass::Any => vec![NON_ASCII],
+        CharacterClass::Range(r) => clip(&[(*r.start() as u32, *r.end() as u32)]),
+        CharacterClass::Set(s) => s
+            .iter()
+            .map(non_ascii_ranges)
+            .fold(vec![], |acc, ranges| union(&acc, &ranges)),
+        CharacterClass::Unset(s) => complement(
+            &s.iter()
+                .map(non_ascii_ranges)
+                .fold(vec![], |acc, ranges| union(&acc, &ranges)),
+        ),
+        CharacterClass::Lowercase(_) | CharacterClass::Uppercase(_) => builtin(class).to_vec(),
+        CharacterClass::AlphaNumeric(is_in)
+        | CharacterClass::Letter(is_in)
+        | CharacterClass::ControlChar(is_in)
+        | CharacterClass::Digit(is_in)
+        | CharacterClass::Printable(is_in)
+        | CharacterClass::Punctuation(is_in)
+        | CharacterClass::WhiteSpace(is_in)
+        | CharacterClass::Hexadecimal(is_in) => {
+            if *is_in {
+                builtin(class).to_vec()
+            } else {
+                complement(builtin(&negate(class)))
+            }
+        }
+    }
+}
+
+/// Non-ASCII ranges of a predicate class, computed once per process
+/// by asking the predicate about every code point.
+fn builtin(class: &CharacterClass) -> &'static [(u32, u32)] {
+    static TABLES: [OnceLock<Ranges>; 12] = [const { OnceLock::new() }; 12];
+    let index = match class {
+        CharacterClass::AlphaNumeric(_) => 0,
+        CharacterClass::Letter(_) => 1,
+        CharacterClass::ControlChar(_) => 2,
+        CharacterClass::Digit(_) => 3,
+        CharacterClass::Printable(_) => 4,
+        CharacterClass::Punctuation(_) => 5,
+        CharacterClass::WhiteSpace(_) => 6,
+        CharacterClass::Hexadecimal(_) => 7,
+        CharacterClass::Lowercase(true) => 8,
+        CharacterClass::Lowercase(false) => 9,
+        CharacterClass::Uppercase(true) => 10,
+        CharacterClass::Uppercase(false) => 11,
+        _ => unreachable!("{:?} is not a predicate class", class),
+    };
+    TABLES[index].get_or_init(|| {
+        let mut ranges: Vec<(u32, u32)> = vec![];
+        for c in ('\u{80}'..=char::MAX).filter(|c| class.is_matched(*c)) {
+            let code = c as u32;
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == code => *end = code,
+                _ => ranges.push((code, code)),
+            }
+        }
+        ranges.into()
+    })
+}
+
+fn negate(class: &CharacterClass) -> CharacterClass {
+    match class {
+        CharacterClass::AlphaNumeric(is_in) => CharacterClass::AlphaNumeric(!is_in),
+        CharacterClass::Letter(is_in) => CharacterClass::Letter(!is_in),
+        CharacterClass::ControlChar(is_in) => CharacterClass::ControlChar(!is_in),
+        CharacterClass::Digit(is_in) => CharacterClass::Digit(!is_in),
+        CharacterClass::Printable(is_in) => CharacterClass::Printable(!is_in),
+        CharacterClass::Punctuation(is_in) => CharacterClass::Punctuation(!is_in),
+        CharacterClass::WhiteSpace(is_in) => CharacterClass::WhiteSpace(!is_in),
+        CharacterClass::Hexadecimal(is_in) => CharacterClass::Hexadecimal(!is_in),
+        _ => unreachable!("{:?} has no plain complement", class),
+    }
+}
+
+fn clip(ranges: &[(u32, u32)]) -> Vec<(u32, u32)> {
+    ranges
+        .iter()
+        .filter(|(start, end)| start <= end && *end >= NON_ASCII.0)
+        .map(|&(start, end)| (start.max(NON_ASCII.0), end))
+        .collect()
+}
+
+fn union(a: &[(u32, u32)], b: &[(u32, u32)]) -> Vec<(u32, u32)> {
+    let mut all = [a, b].concat();
+    all.sort_unstable();
+    let mut ranges: Vec<(u32, u32)> = vec![];
+    for (start, end) in all {
+        match ranges.last_mut() {
+            Some((_, last)) if start <= *last + 1 => *last = (*last).max(end),
+            _ => ranges.push((start, end)),
+        }
+    }
+    ranges
+}
+
+fn complement(ranges: &[(u32, u32)]) -> Vec<(u32, u32)> {
+    let mut result = vec![];
+    let mut next = NON_ASCII.0;
+    for &(start, end) in ranges {
+        if start > next {
+            result.push((next, start - 1));
+        }
+        next = end + 1;
+    }
+    if next <= NON_ASCII.1 {
+        result.push((next, NON_ASCII.1));
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bytecode::character_class::CharacterClass::*;
+
+    #[test]
+    fn same_as_predicates() {
+        let classes = [
+            Literal('ж'),
+            Any,
+            AlphaNumeric(true),
+            Letter(false),
+            ControlChar(true),
+            Digit(false),
+            Printable(true),
+            Lowercase(true),
+            Lowercase(false),
+            Punctuation(true),
+            WhiteSpace(true),
+            Uppercase(false),
+            Hexadecimal(true),
+            Range('a'..='я'),
+            Set([Digit(true), Literal('_'), Range('α'..='ω')].into()),
+            Unset([Letter(true), WhiteSpace(true), Literal('€')].into()),
+        ];
+        let tables = classes.iter().map(ClassTable::new).collect::<Vec<_>>();
+        let chars = ('\0'..='\u{3000}')
+            .chain(('\u{3000}'..=char::MAX).step_by(97))
+            .chain(['€', '\u{10FFFF}']);
+        for c in chars {
+            for (class, table) in classes.iter().zip(&tables) {
+                assert_eq!(
+                    class.is_matched(c),
+                    table.is_matched(c),
+                    "{:?} for {:?}",
+                    class,
+                    c
+                );
+            }
+        }
+    }
+}
@@ -1,13 +1,41 @@
 use super::character_class::CharacterClass;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Code {
     Char(CharacterClass),
+    /// A run of literal chars.
+    Chars(Box<[char]>),
     Captured(usize),
     Border(char, char),
     Frontier(CharacterClass),
     Jmp(usize),
-    Split { x: usize, y: usize },
+    Split {
+        x: usize,
+        y: usize,
+    },
     Save(usize),
+    /// `*` (`min` is 0) and `+` (`min` is 1): takes as many chars as possible,
+    /// then gives them back one by one.
+    MaxExpand {
+        class: CharacterClass,
+        min: usize,
+    },
+    /// `-`: takes as few chars as possible, then one more on each failure.
+    MinExpand(CharacterClass),
+    /// `?`: tries with the char first, then without it.
+    Optional(CharacterClass),
     Match,
 }
+
+impl Code {
+    pub fn class(&self) -> Option<&CharacterClass> {
+        match self {
+            Code::Char(c)
+            | Code::Frontier(c)
+            | Code::MaxExpand { class: c, .. }
+            | Code::MinExpand(c)
+            | Code::Optional(c) => Some(c),
+            _ => None,
+        }
+    }
+}
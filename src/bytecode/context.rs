@@ -1,24 +1,114 @@
 use crate::input::Input;
 
-use super::code::Code;
+use super::{class_table::ClassTable, code::Code};
 use std::ops::Range;
+
+/// Buffers which survive between matches: the decoded subject and the save slots.
+/// Keep one around and pass it to [`crate::regex::Regex::captures_read_with`]
+/// to match without allocating.
+#[derive(Debug, Default)]
+pub struct Scratch {
+    chars: Vec<(usize, char)>,
+    saved: Vec<usize>,
+}
+
+impl Scratch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes `text`, which has been appended at byte `at` of the subject
+    /// the buffered chars belong to.
+    pub(crate) fn push_decoded(&mut self, at: usize, text: &str) {
+        self.chars
+            .extend(text.char_indices().map(|(i, c)| (at + i, c)));
+    }
+
+    /// Drops the chars before byte `len` of the subject, which has lost them.
+    pub(crate) fn drain_decoded(&mut self, len: usize) {
+        let n = self.chars.partition_point(|&(i, _)| i < len);
+        self.chars.drain(..n);
+        self.chars.iter_mut().for_each(|(i, _)| *i -= len);
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct Context<'a> {
     pub program: &'a [Code],
+    pub tables: &'a [ClassTable],
     pub input: Input<'a>,
     pub program_counter: usize,
     pub subj_pointer: usize,
-    pub saved: Box<[usize]>,
+    pub saved: Vec<usize>,
+    pub track_captures: bool,
+    pub anchor_end: bool,
+    /// Set when a thread needed a char past the end of the subject.
+    pub hit_end: bool,
 }
 
 impl<'a> Context<'a> {
-    pub fn new(program: &'a [Code], input: Input<'a>, captures: usize) -> Self {
+    pub fn with_scratch(
+        program: &'a [Code],
+        tables: &'a [ClassTable],
+        subj: &'a str,
+        captures: usize,
+        scratch: Scratch,
+    ) -> Self {
+        let Scratch { chars, saved } = scratch;
+        Self::with_input(
+            program,
+            tables,
+            Input::with_buffer(subj, chars),
+            captures,
+            saved,
+        )
+    }
+
+    /// Same as [`Context::with_scratch`] for a `scratch` left by [`Context::into_scratch`]
+    /// on the same `subj`, which is not decoded again.
+    pub fn with_decoded(
+        program: &'a [Code],
+        tables: &'a [ClassTable],
+        subj: &'a str,
+        captures: usize,
+        scratch: Scratch,
+    ) -> Self {
+        let Scratch { chars, saved } = scratch;
+        Self::with_input(
+            program,
+            tables,
+            Input::decoded(subj, chars),
+            captures,
+            saved,
+        )
+    }
+
+    fn with_input(
+        program: &'a [Code],
+        tables: &'a [ClassTable],
+        input: Input<'a>,
+        captures: usize,
+        mut saved: Vec<usize>,
+    ) -> Self {
+        saved.clear();
+        saved.resize(2 * captures + 2, 0);
         Self {
             program,
+            tables,
             input,
             program_counter: 0,
             subj_pointer: 0,
-            saved: vec![0; 2 * captures + 2].into_boxed_slice(),
+            saved,
+            track_captures: true,
+            anchor_end: false,
+            hit_end: false,
+        }
+    }
+
+    pub fn into_scratch(self) -> Scratch {
+        Scratch {
+            chars: self.input.into_buffer(),
+            saved: self.saved,
         }
     }
 }
@@ -41,9 +131,8 @@ impl Context<'_> {
         begin..end
     }
 
-    pub fn captured_ranges(&self) -> Box<[Range<usize>]> {
-        (0..self.saved.len() / 2)
-            .map(|n| self.captured_range(n))
-            .collect()
+    pub fn read_captured_ranges(&self, ranges: &mut Vec<Range<usize>>) {
+        ranges.clear();
+        ranges.extend((0..self.saved.len() / 2).map(|n| self.captured_range(n)));
     }
 }
@@ -0,0 +1,575 @@
+use crate::error::DecodeError;
+
+use super::{character_class::CharacterClass, code::Code};
+
+const MAGIC: &[u8; 4] = b"RLP\0";
+const VERSION: u8 = 1;
+/// Sets made by the lexer hold no sets, leave some room and no more.
+const MAX_DEPTH: usize = 8;
+/// Same limit as the lexer's.
+const MAX_CAPTURES: usize = 9;
+
+/// Everything a `Regex` is rebuilt from.
+#[derive(Debug, PartialEq)]
+pub struct Encoded {
+    pub code: Vec<Code>,
+    pub anchor_start: bool,
+    pub anchor_end: bool,
+    pub captures: usize,
+}
+
+/// Layout: magic, version, anchors as bit flags, capture count, instruction count
+/// and the instructions. Numbers are little-endian `u32`, every instruction and
+/// class starts with a tag byte.
+pub fn encode(program: &Encoded) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes.push(program.anchor_start as u8 | (program.anchor_end as u8) << 1);
+    put(&mut bytes, program.captures);
+    put(&mut bytes, program.code.len());
+    for code in &program.code {
+        encode_code(&mut bytes, code);
+    }
+    bytes
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Encoded, DecodeError> {
+    let mut reader = Reader { bytes };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(DecodeError::BadHeader);
+    }
+    match reader.byte()? {
+        VERSION => {}
+        version => return Err(DecodeError::UnsupportedVersion(version)),
+    }
+    let flags = reader.byte()?;
+    if flags > 3 {
+        return Err(DecodeError::BadHeader);
+    }
+    let captures = reader.number()?;
+    if captures > MAX_CAPTURES {
+        return Err(DecodeError::BadHeader);
+    }
+    let len = reader.number()?;
+    let code = (0..len)
+        .map(|_| reader.code())
+        .collect::<Result<Vec<_>, _>>()?;
+    if !reader.bytes.is_empty() {
+        return Err(DecodeError::TrailingBytes);
+    }
+    Ok(Encoded {
+        code,
+        anchor_start: flags & 1 == 1,
+        anchor_end: flags & 2 == 2,
+        captures,
+    })
+}
+
+/// Checks that running the program cannot index out of bounds or recurse forever:
+/// the program ends with `Match` as compiled ones do, every target is an
+/// instruction, every slot and back-reference exists, no instruction falls
+/// through past the end, no loop may go round without consuming a char and
+/// every capture is saved in order before `Match`.
+pub fn verify(code: &[Code], captures: usize) -> Result<(), DecodeError> {
+    if !code.contains(&Code::Match) {
+        return Err(DecodeError::NoMatch);
+    }
+    for (pc, instruction) in code.iter().enumerate() {
+        match instruction {
+            Code::Jmp(x) => check_target(pc, *x, code.len())?,
+            Code::Split { x, y } => {
+                check_target(pc, *x, code.len())?;
+                check_target(pc, *y, code.len())?;
+            }
+            Code::Save(slot) if *slot >= 2 * captures + 2 => {
+                return Err(DecodeError::BadSaveSlot { pc, slot: *slot })
+            }
+            Code::Captured(n) if *n == 0 || *n > captures => {
+                return Err(DecodeError::NoSuchCapture { pc, capture: *n })
+            }
+            _ => {}
+        }
+        if falls_through(instruction) && pc + 1 == code.len() {
+            return Err(DecodeError::FallsOffEnd { pc });
+        }
+    }
+    // Compiled programs end with `Match` even when nothing reaches it,
+    // the DFAs rely on it to stop looking for successors.
+    if code.last() != Some(&Code::Match) {
+        return Err(DecodeError::NoMatch);
+    }
+    check_empty_loops(code)?;
+    check_saves(code, captures)
+}
+
+fn check_target(pc: usize, target: usize, len: usize) -> Result<(), DecodeError> {
+    if target < len {
+        Ok(())
+    } else {
+        Err(DecodeError::JumpOutOfRange { pc, target })
+    }
+}
+
+fn falls_through(code: &Code) -> bool {
+    match code {
+        Code::Jmp(_) | Code::Split { .. } | Code::Match => false,
+        Code::Char(CharacterClass::Set(s)) => !s.is_empty(),
+        _ => true,
+    }
+}
+
+/// Instructions a thread may go on to from `pc`.
+fn successors(code: &[Code], pc: usize) -> Vec<usize> {
+    match &code[pc] {
+        Code::Jmp(x) => vec![*x],
+        Code::Split { x, y } => vec![*x, *y],
+        code if falls_through(code) => vec![pc + 1],
+        _ => vec![],
+    }
+}
+
+/// Makes sure no capture can end up with its start after its end, or unset when
+/// the program matches: on every path a capture is opened before it is closed,
+/// never reopened once it may have been closed, and all are saved before `Match`.
+/// Bit `slot` of `must[pc]` is set when every path to `pc` saves the slot,
+/// of `may[pc]` when some path does.
+fn check_saves(code: &[Code], captures: usize) -> Result<(), DecodeError> {
+    let all = (1u32 << (2 * captures + 2)) - 1;
+    let mut must = vec![all; code.len()];
+    let mut may = vec![0u32; code.len()];
+    let mut reached = vec![false; code.len()];
+    must[0] = 0;
+    reached[0] = true;
+    let mut pending = vec![0];
+    while let Some(pc) = pending.pop() {
+        let (mut saved, mut maybe_saved) = (must[pc], may[pc]);
+        if let Code::Save(slot) = code[pc] {
+            saved |= 1 << slot;
+            maybe_saved |= 1 << slot;
+        }
+        for next in successors(code, pc) {
+            let (m, y) = (must[next] & saved, may[next] | maybe_saved);
+            if !reached[next] || m != must[next] || y != may[next] {
+                reached[next] = true;
+                must[next] = m;
+                may[next] = y;
+                pending.push(next);
+            }
+        }
+    }
+    for (pc, instruction) in code.iter().enumerate().filter(|&(pc, _)| reached[pc]) {
+        match *instruction {
+            Code::Save(slot) if slot % 2 == 1 && must[pc] & 1 << (slot - 1) == 0 => {
+                return Err(DecodeError::UnsavedSlot { pc, slot: slot - 1 })
+            }
+            Code::Save(slot) if slot % 2 == 0 && may[pc] & 1 << (slot + 1) != 0 => {
+                return Err(DecodeError::ReopenedCapture { pc, slot })
+            }
+            Code::Match if must[pc] != all => {
+                let slot = (all & !must[pc]).trailing_zeros() as usize;
+                return Err(DecodeError::UnsavedSlot { pc, slot });
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Successors reachable without consuming a char.
+fn empty_successors(code: &[Code], pc: usize) -> Vec<usize> {
+    match &code[pc] {
+        Code::Jmp(x) => vec![*x],
+        Code::Split { x, y } => vec![*x, *y],
+        Code::Chars(s) if s.is_empty() => vec![pc + 1],
+        Code::Save(_)
+        | Code::Frontier(_)
+        | Code::Captured(_)
+        | Code::MinExpand(_)
+        | Code::Optional(_)
+        | Code::MaxExpand { min: 0, .. } => vec![pc + 1],
+        _ => vec![],
+    }
+}
+
+fn check_empty_loops(code: &[Code]) -> Result<(), DecodeError> {
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        New,
+        OnStack,
+        Done,
+    }
+    let mut marks = vec![Mark::New; code.len()];
+    for root in 0..code.len() {
+        if marks[root] != Mark::New {
+            continue;
+        }
+        marks[root] = Mark::OnStack;
+        let mut stack = vec![(root, empty_successors(code, root))];
+        while let Some((pc, successors)) = stack.last_mut() {
+            match successors.pop() {
+                Some(next) => match marks[next] {
+                    Mark::OnStack => return Err(DecodeError::EmptyLoop { pc: next }),
+                    Mark::New => {
+                        marks[next] = Mark::OnStack;
+                        stack.push((next, empty_successors(code, next)));
+                    }
+                    Mark::Done => {}
+                },
+                None => {
+                    marks[*pc] = Mark::Done;
+                    stack.pop();
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn put(bytes: &mut Vec<u8>, n: usize) {
+    let n = u32::try_from(n).expect("Program is too large to encode.");
+    bytes.extend(n.to_le_bytes());
+}
+
+fn put_char(bytes: &mut Vec<u8>, c: char) {
+    put(bytes, c as usize)
+}
+
+fn encode_code(bytes: &mut Vec<u8>, code: &Code) {
+    match code {
+        Code::Char(class) => {
+            bytes.push(0);
+            encode_class(bytes, class);
+        }
+        Code::Chars(s) => {
+            bytes.push(1);
+            put(bytes, s.len());
+            s.iter().for_each(|c| put_char(bytes, *c));
+        }
+        Code::Captured(n) => {
+            bytes.push(2);
+            put(bytes, *n);
+        }
+        Code::Border(x, y) => {
+            bytes.push(3);
+            put_char(bytes, *x);
+            put_char(bytes, *y);
+        }
+        Code::Frontier(class) => {
+            bytes.push(4);
+            encode_class(bytes, class);
+        }
+        Code::Jmp(x) => {
+            bytes.push(5);
+            put(bytes, *x);
+        }
+        Code::Split { x, y } => {
+            bytes.push(6);
+            put(bytes, *x);
+            put(bytes, *y);
+        }
+        Code::Save(slot) => {
+            bytes.push(7);
+            put(bytes, *slot);
+        }
+        Code::MaxExpand { class, min } => {
+            bytes.push(8);
+            encode_class(bytes, class);
+            put(bytes, *min);
+        }
+        Code::MinExpand(class) => {
+            bytes.push(9);
+            encode_class(bytes, class);
+        }
+        Code::Optional(class) => {
+            bytes.push(10);
+            encode_class(bytes, class);
+        }
+        Code::Match => bytes.push(11),
+    }
+}
+
+fn encode_class(bytes: &mut Vec<u8>, class: &CharacterClass) {
+    let (tag, is_in) = match class {
+        CharacterClass::Literal(c) => {
+            bytes.push(0);
+            return put_char(bytes, *c);
+        }
+        CharacterClass::Any => return bytes.push(1),
+        CharacterClass::AlphaNumeric(is_in) => (2, is_in),
+        CharacterClass::Letter(is_in) => (3, is_in),
+        CharacterClass::ControlChar(is_in) => (4, is_in),
+        CharacterClass::Digit(is_in) => (5, is_in),
+        CharacterClass::Printable(is_in) => (6, is_in),
+        CharacterClass::Lowercase(is_in) => (7, is_in),
+        CharacterClass::Punctuation(is_in) => (8, is_in),
+        CharacterClass::WhiteSpace(is_in) => (9, is_in),
+        CharacterClass::Uppercase(is_in) => (10, is_in),
+        CharacterClass::Hexadecimal(is_in) => (11, is_in),
+        CharacterClass::Range(r) => {
+            bytes.push(12);
+            put_char(bytes, *r.start());
+            return put_char(bytes, *r.end());
+        }
+        CharacterClass::Set(s) | CharacterClass::Unset(s) => {
+            bytes.push(if matches!(class, CharacterClass::Set(_)) {
+                13
+            } else {
+                14
+            });
+            put(bytes, s.len());
+            return s.iter().for_each(|item| encode_class(bytes, item));
+        }
+    };
+    bytes.push(tag);
+    bytes.push(*is_in as u8);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() < n {
+            return Err(DecodeError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn number(&mut self) -> Result<usize, DecodeError> {
+        let bytes = self.take(4)?.try_into().unwrap();
+        Ok(u32::from_le_bytes(bytes) as usize)
+    }
+
+    fn char(&mut self) -> Result<char, DecodeError> {
+        let code = self.number()? as u32;
+        char::from_u32(code).ok_or(DecodeError::BadChar(code))
+    }
+
+    fn bool(&mut self) -> Result<bool, DecodeError> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(DecodeError::BadTag(tag)),
+        }
+    }
+
+    /// Number of items to read, checked against what is left so that
+    /// a bogus length cannot make us allocate a lot.
+    fn len(&mut self) -> Result<usize, DecodeError> {
+        let len = self.number()?;
+        if len > self.bytes.len() {
+            return Err(DecodeError::Truncated);
+        }
+        Ok(len)
+    }
+
+    fn code(&mut self) -> Result<Code, DecodeError> {
+        Ok(match self.byte()? {
+            0 => Code::Char(self.class(0)?),
+            1 => {
+                let len = self.len()?;
+                Code::Chars((0..len).map(|_| self.char()).collect::<Result<_, _>>()?)
+            }
+            2 => Code::Captured(self.number()?),
+            3 => Code::Border(self.char()?, self.char()?),
+            4 => Code::Frontier(self.class(0)?),
+            5 => Code::Jmp(self.number()?),
+            6 => Code::Split {
+                x: self.number()?,
+                y: self.number()?,
+            },
+            7 => Code::Save(self.number()?),
+            8 => Code::MaxExpand {
+                class: self.class(0)?,
+                min: self.number()?,
+            },
+            9 => Code::MinExpand(self.class(0)?),
+            10 => Code::Optional(self.class(0)?),
+            11 => Code::Match,
+            tag => return Err(DecodeError::BadTag(tag)),
+        })
+    }
+
+    fn class(&mut self, depth: usize) -> Result<CharacterClass, DecodeError> {
+        if depth > MAX_DEPTH {
+            return Err(DecodeError::TooDeep);
+        }
+        Ok(match self.byte()? {
+            0 => CharacterClass::Literal(self.char()?),
+            1 => CharacterClass::Any,
+            2 => CharacterClass::AlphaNumeric(self.bool()?),
+            3 => CharacterClass::Letter(self.bool()?),
+            4 => CharacterClass::ControlChar(self.bool()?),
+            5 => CharacterClass::Digit(self.bool()?),
+            6 => CharacterClass::Printable(self.bool()?),
+            7 => CharacterClass::Lowercase(self.bool()?),
+            8 => CharacterClass::Punctuation(self.bool()?),
+            9 => CharacterClass::WhiteSpace(self.bool()?),
+            10 => CharacterClass::Uppercase(self.bool()?),
+            11 => CharacterClass::Hexadecimal(self.bool()?),
+            12 => CharacterClass::Range(self.char()?..=self.char()?),
+            tag @ (13 | 14) => {
+                let len = self.len()?;
+                let items = (0..len)
+                    .map(|_| self.class(depth + 1))
+                    .collect::<Result<_, _>>()?;
+                if tag == 13 {
+                    CharacterClass::Set(items)
+                } else {
+                    CharacterClass::Unset(items)
+                }
+            }
+            tag => return Err(DecodeError::BadTag(tag)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bytecode::{character_class::CharacterClass::*, code::Code::*};
+
+    #[test]
+    fn round_trip() {
+        let program = Encoded {
+            code: vec![
+                Save(0),
+                Chars(['a', 'ж'].into()),
+                Split { x: 3, y: 4 },
+                Char(Unset(
+                    [Digit(true), Range('α'..='ω'), Set([Any].into())].into(),
+                )),
+                Save(2),
+                MaxExpand {
+                    class: Lowercase(false),
+                    min: 1,
+                },
+                Save(3),
+                Captured(1),
+                Border('(', ')'),
+                Frontier(Set([WhiteSpace(true)].into())),
+                MinExpand(Literal('-')),
+                Optional(Hexadecimal(false)),
+                Jmp(13),
+                Save(1),
+                Match,
+            ],
+            anchor_start: true,
+            anchor_end: false,
+            captures: 1,
+        };
+        let bytes = encode(&program);
+        assert_eq!(Ok(&program), decode(&bytes).as_ref());
+        assert_eq!(Ok(()), verify(&program.code, program.captures));
+        for n in 0..bytes.len() {
+            assert!(decode(&bytes[..n]).is_err());
+        }
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert_eq!(Err(DecodeError::TrailingBytes), decode(&longer));
+    }
+
+    #[test]
+    fn rejects_unsafe_programs() {
+        let cases = [
+            (
+                vec![Jmp(2), Match],
+                DecodeError::JumpOutOfRange { pc: 0, target: 2 },
+            ),
+            (
+                vec![Save(4), Match],
+                DecodeError::BadSaveSlot { pc: 0, slot: 4 },
+            ),
+            (
+                vec![Captured(2), Match],
+                DecodeError::NoSuchCapture { pc: 0, capture: 2 },
+            ),
+            (vec![Match, Char(Any)], DecodeError::FallsOffEnd { pc: 1 }),
+            (vec![Save(0), Save(1)], DecodeError::NoMatch),
+            (vec![Match, Char(Set([].into()))], DecodeError::NoMatch),
+            (
+                vec![Split { x: 1, y: 3 }, Save(0), Jmp(0), Match],
+                DecodeError::EmptyLoop { pc: 0 },
+            ),
+            (
+                vec![Char(Any), Char(Any), Save(1), Match],
+                DecodeError::UnsavedSlot { pc: 2, slot: 0 },
+            ),
+            (
+                vec![Save(0), Char(Any), Match],
+                DecodeError::UnsavedSlot { pc: 2, slot: 1 },
+            ),
+            (
+                vec![Save(0), Save(1), Save(0), Match],
+                DecodeError::ReopenedCapture { pc: 2, slot: 0 },
+            ),
+            (
+                vec![Save(0), Save(3), Char(Any), Save(2), Save(1), Match],
+                DecodeError::UnsavedSlot { pc: 1, slot: 2 },
+            ),
+            (
+                vec![
+                    Save(0),
+                    Split { x: 2, y: 4 },
+                    Save(2),
+                    Save(3),
+                    Save(1),
+                    Match,
+                ],
+                DecodeError::UnsavedSlot { pc: 5, slot: 2 },
+            ),
+        ];
+        for (code, error) in cases {
+            assert_eq!(Err(error), verify(&code, 1), "{:?}", code);
+        }
+        assert_eq!(
+            Ok(()),
+            verify(
+                &[
+                    Save(0),
+                    Split { x: 2, y: 4 },
+                    Char(Any),
+                    Jmp(1),
+                    Save(1),
+                    Match
+                ],
+                0
+            )
+        );
+        assert_eq!(Ok(()), verify(&[Char(Set([].into())), Match], 0));
+    }
+
+    #[test]
+    fn rejects_oversized_programs() {
+        let mut chars = Encoded {
+            code: vec![Save(0)],
+            anchor_start: false,
+            anchor_end: false,
+            captures: 0,
+        };
+        chars.code.extend(vec![Char(Any); 1 << 12]);
+        chars.code.extend([Save(1), Match]);
+        assert_eq!(Ok(&chars), decode(&encode(&chars)).as_ref());
+        assert_eq!(Ok(()), verify(&chars.code, 0));
+        let mut claimed = encode(&chars);
+        claimed[10..14].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(Err(DecodeError::Truncated), decode(&claimed));
+
+        let mut class = Any;
+        for _ in 0..=MAX_DEPTH + 1 {
+            class = Set([class].into());
+        }
+        let nested = Encoded {
+            code: vec![Char(class), Match],
+            ..chars
+        };
+        assert_eq!(Err(DecodeError::TooDeep), decode(&encode(&nested)));
+    }
+}
@@ -1,6 +1,9 @@
 use itertools::Itertools;
 
-use crate::bytecode::character_class::CharacterClass;
+use crate::{
+    bytecode::character_class::CharacterClass,
+    error::{Error, ErrorKind},
+};
 
 #[derive(Debug, PartialEq)]
 pub enum PatternElement {
@@ -15,6 +18,19 @@ pub enum PatternElement {
     SaveClose(usize),
 }
 
+impl PatternElement {
+    /// Only single char items may be followed by a quantifier.
+    pub fn is_single_char(&self) -> bool {
+        matches!(
+            self,
+            PatternElement::AnyChar
+                | PatternElement::Literal(_)
+                | PatternElement::CharacterClass(_)
+                | PatternElement::CharacterSet(_)
+        )
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Quantifier {
     ExactlyOne,
@@ -24,101 +40,124 @@ pub enum Quantifier {
     ZeroOrManyUngreedy,
 }
 
-pub fn lex(re: &str) -> impl Iterator<Item = (PatternElement, Quantifier)> + '_ {
+type Pattern<'a> = std::iter::Peekable<std::str::CharIndices<'a>>;
+
+pub type Lexed = (usize, PatternElement, Quantifier);
+
+/// Splits the pattern into items along with their byte offsets.
+/// Stops after the first error.
+pub fn try_lex(re: &str) -> impl Iterator<Item = Result<Lexed, Error>> + '_ {
     let mut saves = vec![];
     let mut captures = 0;
-    let re = re.chars().peekable();
+    let mut failed = false;
+    let re = re.char_indices().peekable();
     re.batching(move |re| {
-        let lex = match re.next()? {
-            '.' => PatternElement::AnyChar,
-            '[' => PatternElement::CharacterSet(make_character_set(re)),
-            '(' => {
-                captures += 1;
-                if captures > 9 {
-                    panic!("Too many captures.")
-                }
-                saves.push(captures);
-                PatternElement::SaveOpen(captures)
-            }
-            ')' => {
-                let captured = saves.pop().unwrap();
-                PatternElement::SaveClose(captured)
+        if failed {
+            return None;
+        }
+        let lexed = lex_one(re, &mut saves, &mut captures)?;
+        failed = lexed.is_err();
+        Some(lexed)
+    })
+}
+
+fn lex_one(
+    re: &mut Pattern,
+    saves: &mut Vec<usize>,
+    captures: &mut usize,
+) -> Option<Result<Lexed, Error>> {
+    let (position, c) = re.next()?;
+    let error = |kind| Some(Err(Error::new(position, kind)));
+    let lex = match c {
+        '.' => PatternElement::AnyChar,
+        '[' => match make_character_set(re) {
+            Ok(set) => PatternElement::CharacterSet(set),
+            Err(kind) => return error(kind),
+        },
+        '(' => {
+            *captures += 1;
+            if *captures > 9 {
+                return error(ErrorKind::TooManyCaptures);
             }
-            '%' => match re.next()? {
-                d @ ('1'..='9') => {
-                    let d = d.to_digit(10).unwrap() as usize;
-                    if saves.contains(&d) || d > captures {
-                        panic!("You have no such capture {}", d);
-                    }
-                    PatternElement::Captured(d)
-                }
-                'b' => match (re.next()?, re.next()?) {
-                    (x, y) if x != y => PatternElement::Border(x, y),
-                    _ => panic!("Border chars must be different"),
+            saves.push(*captures);
+            PatternElement::SaveOpen(*captures)
+        }
+        ')' => match saves.pop() {
+            Some(captured) => PatternElement::SaveClose(captured),
+            None => return error(ErrorKind::UnmatchedClose),
+        },
+        '%' => match re.next().map(|(_, c)| c) {
+            None => return error(ErrorKind::EndsWithPercent),
+            Some(d @ '1'..='9') => PatternElement::Captured(d.to_digit(10).unwrap() as usize),
+            Some('b') => match (re.next(), re.next()) {
+                (Some((_, x)), Some((_, y))) if x != y => PatternElement::Border(x, y),
+                _ => return error(ErrorKind::BorderChars),
+            },
+            Some('f') => match re.next() {
+                Some((_, '[')) => match make_character_set(re) {
+                    Ok(set) => PatternElement::Frontier(set),
+                    Err(kind) => return error(kind),
                 },
-                'f' => {
-                    if let Some('[') = re.next() {
-                        PatternElement::Frontier(make_character_set(re))
-                    } else {
-                        panic!("After %f must be '['")
-                    }
-                }
-                c => PatternElement::CharacterClass(char_to_class(c)),
+                _ => return error(ErrorKind::FrontierWithoutSet),
+            },
+            Some(c) => match char_to_class(c) {
+                Some(class) => PatternElement::CharacterClass(class),
+                None => return error(ErrorKind::IllegalEscape(c)),
             },
-            c => PatternElement::Literal(c),
-        };
-        let quantifier = match re.peek() {
-            Some(c) if ['*', '+', '-', '?'].contains(c) => match re.next().unwrap() {
+        },
+        c => PatternElement::Literal(c),
+    };
+    let quantifier = match re.peek() {
+        Some((_, c)) if lex.is_single_char() && ['*', '+', '-', '?'].contains(c) => {
+            match re.next().unwrap().1 {
                 '*' => Quantifier::ZeroOrManyGreedy,
                 '+' => Quantifier::OneOrMany,
                 '-' => Quantifier::ZeroOrManyUngreedy,
                 '?' => Quantifier::ZeroOrOne,
                 _ => unreachable!(),
-            },
-            _ => Quantifier::ExactlyOne,
-        };
-        Some((lex, quantifier))
-    })
+            }
+        }
+        _ => Quantifier::ExactlyOne,
+    };
+    Some(Ok((position, lex, quantifier)))
 }
 
-fn make_character_set<I>(re: &mut std::iter::Peekable<I>) -> CharacterClass
-where
-    I: Iterator<Item = char>,
-{
+/// Reads a set after its opening '['.
+fn make_character_set(re: &mut Pattern) -> Result<CharacterClass, ErrorKind> {
     match re.peek() {
-        Some(c) if c == &'^' => {
+        Some((_, '^')) => {
             re.next();
-            CharacterClass::Unset(to_character_set(re))
+            to_character_set(re).map(CharacterClass::Unset)
         }
-        _ => CharacterClass::Set(to_character_set(re)),
+        _ => to_character_set(re).map(CharacterClass::Set),
     }
 }
 
-fn to_character_set<I>(re: &mut std::iter::Peekable<I>) -> Box<[CharacterClass]>
-where
-    I: Iterator<Item = char>,
-{
-    re.batching(|re| {
-        Some(match re.next()? {
-            '%' => char_to_class(re.next()?),
-            ']' => return None,
+fn to_character_set(re: &mut Pattern) -> Result<Box<[CharacterClass]>, ErrorKind> {
+    let mut set = vec![];
+    let next = |re: &mut Pattern| re.next().map(|(_, c)| c).ok_or(ErrorKind::MissingBracket);
+    loop {
+        set.push(match next(re)? {
+            '%' => {
+                let c = next(re)?;
+                char_to_class(c).ok_or(ErrorKind::IllegalEscape(c))?
+            }
+            ']' => return Ok(set.into()),
             c => {
-                if let Some('-') = re.peek() {
+                if let Some((_, '-')) = re.peek() {
                     re.next().unwrap();
-                    let end = re.next()?;
-                    CharacterClass::Range(c..=end)
+                    CharacterClass::Range(c..=next(re)?)
                 } else {
                     CharacterClass::Literal(c)
                 }
             }
         })
-    })
-    .collect()
+    }
 }
 
-fn char_to_class(c: char) -> CharacterClass {
+fn char_to_class(c: char) -> Option<CharacterClass> {
     let is_in = c.is_ascii_lowercase();
-    match c {
+    Some(match c {
         'w' | 'W' => CharacterClass::AlphaNumeric(is_in),
         'a' | 'A' => CharacterClass::Letter(is_in),
         'c' | 'C' => CharacterClass::ControlChar(is_in),
@@ -130,14 +169,21 @@ fn char_to_class(c: char) -> CharacterClass {
         'u' | 'U' => CharacterClass::Uppercase(is_in),
         'x' | 'X' => CharacterClass::Hexadecimal(is_in),
         c if !c.is_alphanumeric() => CharacterClass::Literal(c),
-        _ => panic!("Illegal char in escaping."),
-    }
+        _ => return None,
+    })
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn lex(re: &str) -> impl Iterator<Item = (PatternElement, Quantifier)> + '_ {
+        try_lex(re).map(|lexed| {
+            let (_, lex, quantifier) = lexed.unwrap();
+            (lex, quantifier)
+        })
+    }
+
     fn run(re: &str, answer: &[(PatternElement, Quantifier)], comment: &str) {
         assert_eq!(
             answer,
@@ -273,4 +319,67 @@ mod test {
             run(re, &answer, "Two lexes");
         }
     }
+
+    #[test]
+    fn quantifier_after_capture_is_literal() {
+        run(
+            r"()-",
+            &[
+                (PatternElement::SaveOpen(1), Quantifier::ExactlyOne),
+                (PatternElement::SaveClose(1), Quantifier::ExactlyOne),
+                (PatternElement::Literal('-'), Quantifier::ExactlyOne),
+            ],
+            "Quantifier after capture",
+        );
+        run(
+            r"%b()*",
+            &[
+                (PatternElement::Border('(', ')'), Quantifier::ExactlyOne),
+                (PatternElement::Literal('*'), Quantifier::ExactlyOne),
+            ],
+            "Quantifier after border",
+        );
+        run(
+            r"(a)%1+",
+            &[
+                (PatternElement::SaveOpen(1), Quantifier::ExactlyOne),
+                (PatternElement::Literal('a'), Quantifier::ExactlyOne),
+                (PatternElement::SaveClose(1), Quantifier::ExactlyOne),
+                (PatternElement::Captured(1), Quantifier::ExactlyOne),
+                (PatternElement::Literal('+'), Quantifier::ExactlyOne),
+            ],
+            "Quantifier after back-reference",
+        );
+        let frontier = lex(r"%f[a]?").collect::<Vec<_>>();
+        assert!(matches!(
+            frontier[0],
+            (PatternElement::Frontier(_), Quantifier::ExactlyOne)
+        ));
+        assert_eq!(
+            (PatternElement::Literal('?'), Quantifier::ExactlyOne),
+            frontier[1]
+        );
+    }
+
+    #[test]
+    fn errors_have_positions() {
+        let cases = [
+            ("ab%", 2, ErrorKind::EndsWithPercent),
+            ("a[bc", 1, ErrorKind::MissingBracket),
+            ("a)", 1, ErrorKind::UnmatchedClose),
+            ("%bxx", 0, ErrorKind::BorderChars),
+            ("%fa", 0, ErrorKind::FrontierWithoutSet),
+            ("жж%q", 4, ErrorKind::IllegalEscape('q')),
+            ("[%q]", 0, ErrorKind::IllegalEscape('q')),
+            ("()()()()()()()()()()", 18, ErrorKind::TooManyCaptures),
+        ];
+        for (re, position, kind) in cases {
+            assert_eq!(
+                Some(Err(Error::new(position, kind))),
+                try_lex(re).find(Result::is_err),
+                "{}",
+                re
+            );
+        }
+    }
 }
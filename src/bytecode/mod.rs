@@ -1,4 +1,7 @@
 pub(crate) mod character_class;
+pub(crate) mod class_table;
 pub(crate) mod code;
 pub(crate) mod context;
+pub(crate) mod encoding;
 pub(crate) mod lexer;
+pub(crate) mod optimizer;
@@ -0,0 +1,262 @@
+use super::{character_class::CharacterClass, code::Code};
+
+/// Peephole passes over a compiled program:
+/// 1. classes which always or never match become `Any` or an empty `Set`,
+///    loops over never matching classes disappear;
+/// 2. `Jmp` and `Split` targets skip over chains of `Jmp`;
+/// 3. runs of literal `Char`s become one `Chars`;
+/// 4. unreachable and no-op instructions are removed and targets renumbered,
+///    until nothing more can be removed.
+pub fn optimize(program: Vec<Code>) -> Vec<Code> {
+    let program = program.into_iter().map(fold_classes).collect::<Vec<_>>();
+    let mut program = merge_literals(thread_jumps(program));
+    loop {
+        let len = program.len();
+        program = remove_dead_code(thread_jumps(program));
+        if program.len() == len {
+            return program;
+        }
+    }
+}
+
+fn fold_classes(code: Code) -> Code {
+    match code {
+        Code::Char(c) => Code::Char(fold_class(c)),
+        Code::Frontier(c) => Code::Frontier(fold_class(c)),
+        Code::Optional(c) => Code::Optional(fold_class(c)),
+        Code::MinExpand(c) => Code::MinExpand(fold_class(c)),
+        Code::MaxExpand { class, min } => match (fold_class(class), min) {
+            (class, 1) if is_never(&class) => Code::Char(class),
+            (class, min) => Code::MaxExpand { class, min },
+        },
+        code => code,
+    }
+}
+
+fn fold_class(class: CharacterClass) -> CharacterClass {
+    match class {
+        CharacterClass::Range(r) if r.start() == r.end() => CharacterClass::Literal(*r.start()),
+        CharacterClass::Set(s) => {
+            let s = fold_items(s);
+            if s.contains(&CharacterClass::Any) {
+                CharacterClass::Any
+            } else if s.len() == 1 {
+                s.into_vec().pop().unwrap()
+            } else {
+                CharacterClass::Set(s)
+            }
+        }
+        CharacterClass::Unset(s) => {
+            let s = fold_items(s);
+            if s.contains(&CharacterClass::Any) {
+                CharacterClass::Set(Box::default())
+            } else if s.is_empty() {
+                CharacterClass::Any
+            } else {
+                CharacterClass::Unset(s)
+            }
+        }
+        class => class,
+    }
+}
+
+fn fold_items(items: Box<[CharacterClass]>) -> Box<[CharacterClass]> {
+    items
+        .into_vec()
+        .into_iter()
+        .filter(|item| !matches!(item, CharacterClass::Range(r) if r.start() > r.end()))
+        .map(fold_class)
+        .collect()
+}
+
+fn is_never(class: &CharacterClass) -> bool {
+    matches!(class, CharacterClass::Set(s) if s.is_empty())
+}
+
+fn thread_jumps(mut program: Vec<Code>) -> Vec<Code> {
+    let target = |program: &[Code], mut pc: usize| {
+        for _ in 0..program.len() {
+            match program[pc] {
+                Code::Jmp(next) => pc = next,
+                _ => break,
+            }
+        }
+        pc
+    };
+    for pc in 0..program.len() {
+        program[pc] = match program[pc] {
+            Code::Jmp(x) => Code::Jmp(target(&program, x)),
+            Code::Split { x, y } => Code::Split {
+                x: target(&program, x),
+                y: target(&program, y),
+            },
+            _ => continue,
+        };
+    }
+    program
+}
+
+fn merge_literals(program: Vec<Code>) -> Vec<Code> {
+    let targets = jump_targets(&program);
+    let mut merged = Vec::with_capacity(program.len());
+    let mut run: Vec<char> = vec![];
+    for (pc, code) in program.into_iter().enumerate() {
+        if targets[pc] || !matches!(code, Code::Char(CharacterClass::Literal(_))) {
+            flush_literals(&mut merged, &mut run);
+        }
+        if let Code::Char(CharacterClass::Literal(c)) = code {
+            run.push(c);
+        } else {
+            merged.push(code);
+        }
+    }
+    flush_literals(&mut merged, &mut run);
+    merged
+}
+
+/// Replaces a run of `n` literals with `Chars` followed by `n - 1` no-op jumps,
+/// so that the program keeps its numbering until dead code is removed.
+fn flush_literals(program: &mut Vec<Code>, run: &mut Vec<char>) {
+    match run.len() {
+        0 => {}
+        1 => program.push(Code::Char(CharacterClass::Literal(run[0]))),
+        n => {
+            program.push(Code::Chars(std::mem::take(run).into()));
+            for _ in 1..n {
+                let pc = program.len();
+                program.push(Code::Jmp(pc + 1));
+            }
+        }
+    }
+    run.clear();
+}
+
+fn remove_dead_code(program: Vec<Code>) -> Vec<Code> {
+    let mut reachable = vec![false; program.len()];
+    let mut stack = vec![0];
+    while let Some(pc) = stack.pop() {
+        if pc >= program.len() || reachable[pc] {
+            continue;
+        }
+        reachable[pc] = true;
+        stack.extend(successors(&program, pc));
+    }
+    // Programs end with `Match` even when a class which never matches cuts it off.
+    if program.last() == Some(&Code::Match) {
+        reachable[program.len() - 1] = true;
+    }
+    let keep = program
+        .iter()
+        .enumerate()
+        .map(|(pc, code)| reachable[pc] && !is_no_op(code, pc))
+        .collect::<Vec<_>>();
+    let mut renumbered = vec![0; program.len() + 1];
+    for pc in 0..program.len() {
+        renumbered[pc + 1] = renumbered[pc] + keep[pc] as usize;
+    }
+    program
+        .into_iter()
+        .enumerate()
+        .filter(|(pc, _)| keep[*pc])
+        .map(|(_, code)| match code {
+            Code::Jmp(x) => Code::Jmp(renumbered[x]),
+            Code::Split { x, y } => Code::Split {
+                x: renumbered[x],
+                y: renumbered[y],
+            },
+            code => code,
+        })
+        .collect()
+}
+
+fn is_no_op(code: &Code, pc: usize) -> bool {
+    match code {
+        Code::Jmp(x) => *x == pc + 1,
+        Code::Optional(c) | Code::MinExpand(c) | Code::MaxExpand { class: c, min: 0 } => {
+            is_never(c)
+        }
+        _ => false,
+    }
+}
+
+fn successors(program: &[Code], pc: usize) -> Vec<usize> {
+    match &program[pc] {
+        Code::Jmp(x) => vec![*x],
+        Code::Split { x, y } => vec![*x, *y],
+        Code::Match => vec![],
+        Code::Char(c) if is_never(c) => vec![],
+        _ => vec![pc + 1],
+    }
+}
+
+fn jump_targets(program: &[Code]) -> Vec<bool> {
+    let mut targets = vec![false; program.len() + 1];
+    for code in program {
+        match code {
+            Code::Jmp(x) => targets[*x] = true,
+            Code::Split { x, y } => {
+                targets[*x] = true;
+                targets[*y] = true;
+            }
+            _ => {}
+        }
+    }
+    targets
+}
+
+#[cfg(test)]
+mod test {
+    use super::optimize;
+    use crate::bytecode::{character_class::CharacterClass::*, code::Code::*};
+
+    #[test]
+    fn merges_literals_and_threads_jumps() {
+        let program = vec![
+            Save(0),
+            Char(Literal('a')),
+            Char(Literal('b')),
+            Split { x: 4, y: 6 },
+            Char(Set([Range('c'..='c')].into())),
+            Jmp(6),
+            Jmp(7),
+            Char(Literal('d')),
+            Char(Literal('e')),
+            Char(Unset([].into())),
+            Save(1),
+            Match,
+        ];
+        assert_eq!(
+            vec![
+                Save(0),
+                Chars(['a', 'b'].into()),
+                Split { x: 3, y: 4 },
+                Char(Literal('c')),
+                Chars(['d', 'e'].into()),
+                Char(Any),
+                Save(1),
+                Match
+            ],
+            optimize(program)
+        );
+    }
+
+    #[test]
+    fn drops_code_after_impossible_class() {
+        let program = vec![
+            Save(0),
+            MaxExpand {
+                class: Set([].into()),
+                min: 0,
+            },
+            Char(Literal('a')),
+            Char(Unset([Any].into())),
+            Char(Literal('b')),
+            Save(1),
+            Match,
+        ];
+        assert_eq!(
+            vec![Save(0), Char(Literal('a')), Char(Set([].into())), Match],
+            optimize(program)
+        );
+    }
+}
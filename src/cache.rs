@@ -0,0 +1,221 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{
+    error::Error,
+    regex::{CompileOptions, Regex},
+};
+
+/// Bounded cache of compiled patterns. The least recently used pattern is
+/// dropped when a new one does not fit. Returned [`Regex`]es share their
+/// program with the cached one.
+#[derive(Debug)]
+pub struct PatternCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub len: usize,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    patterns: HashMap<CompileOptions, HashMap<Box<str>, Entry>>,
+    /// Every cached pattern by its `last_used`, the least recently used first.
+    recency: BTreeMap<u64, (CompileOptions, Box<str>)>,
+    clock: u64,
+    stats: CacheStats,
+}
+
+#[derive(Debug)]
+struct Entry {
+    regex: Regex,
+    last_used: u64,
+}
+
+impl PatternCache {
+    pub fn new(capacity: usize) -> Self {
+        assert!(
+            capacity > 0,
+            "Pattern cache must hold at least one pattern."
+        );
+        Self {
+            capacity,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Panics on a malformed pattern, see [`PatternCache::try_compile`].
+    pub fn compile(&self, re: &str) -> Regex {
+        self.compile_with(re, CompileOptions::default())
+    }
+
+    pub fn compile_with(&self, re: &str, options: CompileOptions) -> Regex {
+        self.try_compile_with(re, options)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as [`PatternCache::compile`] but reports a malformed pattern instead
+    /// of panicking. Failed patterns are not cached.
+    pub fn try_compile(&self, re: &str) -> Result<Regex, Error> {
+        self.try_compile_with(re, CompileOptions::default())
+    }
+
+    /// The pattern is compiled without holding the lock, so other threads can
+    /// use the cache meanwhile; if one of them cached the same pattern first,
+    /// its `Regex` is returned.
+    pub fn try_compile_with(&self, re: &str, options: CompileOptions) -> Result<Regex, Error> {
+        if let Some(regex) = self.lock().get(re, options) {
+            return Ok(regex);
+        }
+        let regex = Regex::try_with_options(re, options)?;
+        let mut inner = self.lock();
+        if let Some(entry) = inner
+            .patterns
+            .get(&options)
+            .and_then(|patterns| patterns.get(re))
+        {
+            return Ok(entry.regex.clone());
+        }
+        if inner.stats.len >= self.capacity {
+            inner.evict();
+        }
+        inner.insert(re, options, regex.clone());
+        Ok(regex)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.lock().stats
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Drops every pattern, statistics are kept.
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.patterns.clear();
+        inner.recency.clear();
+        inner.stats.len = 0;
+    }
+
+    /// A panic while the lock is held cannot leave `Inner` half updated, so a
+    /// poisoned lock is used as is.
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Inner {
+    /// Counts a hit or a miss and marks a found pattern as used.
+    fn get(&mut self, re: &str, options: CompileOptions) -> Option<Regex> {
+        self.clock += 1;
+        let clock = self.clock;
+        let Some(entry) = self
+            .patterns
+            .get_mut(&options)
+            .and_then(|patterns| patterns.get_mut(re))
+        else {
+            self.stats.misses += 1;
+            return None;
+        };
+        let key = self.recency.remove(&entry.last_used).unwrap();
+        self.recency.insert(clock, key);
+        entry.last_used = clock;
+        self.stats.hits += 1;
+        Some(entry.regex.clone())
+    }
+
+    /// Adds a pattern as the most recently used one. The clock moves on so that no
+    /// two patterns share a `last_used`, even when another thread hit one meanwhile.
+    fn insert(&mut self, re: &str, options: CompileOptions, regex: Regex) {
+        self.clock += 1;
+        let last_used = self.clock;
+        self.recency.insert(last_used, (options, re.into()));
+        self.patterns
+            .entry(options)
+            .or_default()
+            .insert(re.into(), Entry { regex, last_used });
+        self.stats.len += 1;
+    }
+
+    fn evict(&mut self) {
+        if let Some((_, (options, re))) = self.recency.pop_first() {
+            let patterns = self.patterns.get_mut(&options).unwrap();
+            patterns.remove(&re);
+            if patterns.is_empty() {
+                self.patterns.remove(&options);
+            }
+            self.stats.len -= 1;
+            self.stats.evictions += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CacheStats, PatternCache};
+    use crate::regex::CompileOptions;
+
+    #[test]
+    fn least_recently_used_is_evicted() {
+        let cache = PatternCache::new(2);
+        let digits = cache.compile("%d+");
+        cache.compile("%a+");
+        assert!(cache.compile("%d+").is_match("42"));
+        cache.compile("%s+");
+        assert_eq!(
+            CacheStats {
+                hits: 1,
+                misses: 3,
+                evictions: 1,
+                len: 2
+            },
+            cache.stats()
+        );
+        cache.compile("%d+");
+        cache.compile("%a+");
+        assert_eq!((2, 4), (cache.stats().hits, cache.stats().misses));
+        assert_eq!(Some(1..3), digits.find("a42"));
+        assert_eq!(2, cache.lock().recency.len());
+    }
+
+    #[test]
+    fn options_are_part_of_the_key() {
+        let cache = PatternCache::new(4);
+        cache.compile("abc");
+        cache.compile_with("abc", CompileOptions { optimize: false });
+        cache.compile("abc");
+        assert_eq!((1, 2), (cache.stats().hits, cache.stats().misses));
+        cache.clear();
+        assert_eq!(0, cache.stats().len);
+        assert!(cache.lock().recency.is_empty());
+    }
+
+    #[test]
+    fn malformed_patterns_are_not_cached() {
+        let cache = PatternCache::new(1);
+        cache.compile("%d+");
+        assert!(cache.try_compile("(").is_err());
+        assert!(std::panic::catch_unwind(|| cache.compile("%")).is_err());
+        assert_eq!(
+            CacheStats {
+                hits: 0,
+                misses: 3,
+                evictions: 0,
+                len: 1
+            },
+            cache.stats()
+        );
+        assert!(cache.compile("%d+").is_match("7"));
+        assert_eq!(1, cache.stats().hits);
+    }
+}
@@ -0,0 +1,334 @@
+use std::fmt::Write;
+
+use crate::{
+    bytecode::{character_class::CharacterClass, code::Code},
+    error::Error,
+    regex::Regex,
+};
+
+/// Rust source of `pub fn name(subj: &str) -> Option<rlp::Match<'_>>` doing what
+/// [`Regex::match_one`] does for `re`. The compiled program becomes a `match` on
+/// the program counter in a loop, with an explicit stack of backtracking points,
+/// so the generated matcher backtracks the way the interpreter does while
+/// walking the subject in place.
+///
+/// Meant for `build.rs`:
+/// ```ignore
+/// let source = rlp::codegen::generate("key_value", "(%a+)=(%d+)").unwrap();
+/// std::fs::write(out_dir.join("matchers.rs"), source).unwrap();
+/// ```
+pub fn generate(name: &str, re: &str) -> Result<String, Error> {
+    let regex = Regex::try_new(re)?;
+    let program = &regex.program;
+    let mut source = String::new();
+    let w = &mut source;
+    let doc = format!("Matcher generated for the Lua pattern `{}`.", re);
+    writeln!(w, "#[doc = {:?}]", doc).unwrap();
+    writeln!(w, "#[allow(unused, clippy::all)]").unwrap();
+    writeln!(
+        w,
+        "pub fn {}(subj: &str) -> Option<::rlp::Match<'_>> {{",
+        name
+    )
+    .unwrap();
+    writeln!(
+        w,
+        "    fn next_char(subj: &str, p: usize) -> Option<char> {{
+        subj[p..].chars().next()
+    }}"
+    )
+    .unwrap();
+    writeln!(
+        w,
+        "    /// Backtracking points are `(state, byte, count)`, state `usize::MAX`
+    /// restores save slot `byte` to `count`.
+    fn run(
+        subj: &str,
+        start: usize,
+        saved: &mut [usize],
+        stack: &mut Vec<(usize, usize, usize)>,
+    ) -> bool {{
+        stack.clear();
+        let (mut pc, mut p, mut n) = (0, start, 0);
+        loop {{
+            let ok = match pc {{"
+    )
+    .unwrap();
+    let retry = program.code.len();
+    for (pc, code) in program.code.iter().enumerate() {
+        writeln!(w, "                {} => {{", pc).unwrap();
+        instruction(w, pc, retry + pc, code, program.anchor_end);
+        writeln!(w, "                }}").unwrap();
+        if let Some(body) = retry_state(pc, retry + pc, code) {
+            writeln!(w, "                {} => {{", retry + pc).unwrap();
+            writeln!(w, "                    {}", body).unwrap();
+            writeln!(w, "                }}").unwrap();
+        }
+    }
+    writeln!(
+        w,
+        "                _ => unreachable!(),
+            }};
+            if ok {{
+                continue;
+            }}
+            loop {{
+                match stack.pop() {{
+                    None => return false,
+                    Some((usize::MAX, slot, old)) => saved[slot] = old,
+                    Some(point) => {{
+                        (pc, p, n) = point;
+                        break;
+                    }}
+                }}
+            }}
+        }}
+    }}"
+    )
+    .unwrap();
+    writeln!(
+        w,
+        "    let mut saved = [0usize; {}];",
+        2 * program.captures + 2
+    )
+    .unwrap();
+    writeln!(w, "    let mut stack = Vec::new();").unwrap();
+    writeln!(w, "    let mut start = 0;").unwrap();
+    writeln!(w, "    loop {{").unwrap();
+    writeln!(w, "        if run(subj, start, &mut saved, &mut stack) {{").unwrap();
+    writeln!(
+        w,
+        "            let captures = saved.chunks(2).map(|r| r[0]..r[1]).collect();"
+    )
+    .unwrap();
+    writeln!(
+        w,
+        "            return Some(::rlp::Match {{ subj, captures, position_captures: {} }});",
+        program.position_captures
+    )
+    .unwrap();
+    writeln!(w, "        }}").unwrap();
+    if program.anchor_start {
+        writeln!(w, "        return None;").unwrap();
+    } else {
+        writeln!(w, "        start += next_char(subj, start)?.len_utf8();").unwrap();
+    }
+    writeln!(w, "    }}").unwrap();
+    writeln!(w, "}}").unwrap();
+    Ok(source)
+}
+
+/// Arm of state `pc`, a block evaluating to `false` when the thread fails.
+/// `p` is a byte offset in the subject.
+fn instruction(w: &mut String, pc: usize, retry: usize, code: &Code, anchor_end: bool) {
+    let next = pc + 1;
+    let body = match code {
+        Code::Char(class) => format!(
+            "match next_char(subj, p) {{
+                        Some(c) if {} => {{
+                            p += c.len_utf8();
+                            pc = {};
+                            true
+                        }}
+                        _ => false,
+                    }}",
+            condition(class),
+            next
+        ),
+        Code::Chars(chars) => {
+            let literal = chars.iter().collect::<String>();
+            format!(
+                "subj[p..].starts_with({:?}) && {{
+                        p += {};
+                        pc = {};
+                        true
+                    }}",
+                literal,
+                literal.len(),
+                next
+            )
+        }
+        Code::Captured(n) => format!(
+            "let captured = &subj[saved[{}]..saved[{}]];
+                    subj[p..].starts_with(captured) && {{
+                        p += captured.len();
+                        pc = {};
+                        true
+                    }}",
+            2 * n,
+            2 * n + 1,
+            next
+        ),
+        Code::Border(x, y) => format!(
+            "let mut counter = 0;
+                    let mut end = None;
+                    for (i, c) in subj[p..].char_indices() {{
+                        if i == 0 && c != {0:?} {{
+                            break;
+                        }}
+                        if c == {0:?} {{
+                            counter += 1;
+                        }} else if c == {1:?} {{
+                            counter -= 1;
+                        }}
+                        if counter == 0 {{
+                            end = Some(p + i + c.len_utf8());
+                            break;
+                        }}
+                    }}
+                    end.map(|end| {{
+                        p = end;
+                        pc = {2};
+                    }})
+                    .is_some()",
+            x, y, next
+        ),
+        Code::Frontier(class) => format!(
+            "let prev = subj[..p].chars().next_back().unwrap_or('\\0');
+                    let current = next_char(subj, p).unwrap_or('\\0');
+                    let is_matched = |c: char| {};
+                    is_matched(current) && !is_matched(prev) && {{
+                        pc = {};
+                        true
+                    }}",
+            condition(class),
+            next
+        ),
+        Code::Jmp(x) => format!(
+            "pc = {};
+                    true",
+            x
+        ),
+        Code::Split { x, y } => format!(
+            "stack.push(({}, p, 0));
+                    pc = {};
+                    true",
+            y, x
+        ),
+        Code::Save(slot) => format!(
+            "stack.push((usize::MAX, {0}, saved[{0}]));
+                    saved[{0}] = p;
+                    pc = {1};
+                    true",
+            slot, next
+        ),
+        Code::MaxExpand { class, min } => format!(
+            "let (mut end, mut count) = (p, 0);
+                    while let Some(c) = next_char(subj, end) {{
+                        if !({}) {{
+                            break;
+                        }}
+                        end += c.len_utf8();
+                        count += 1;
+                    }}
+                    {}{{
+                        stack.push(({}, end, count));
+                        p = end;
+                        pc = {};
+                        true
+                    }}",
+            condition(class),
+            if *min > 0 {
+                format!("count >= {} && ", min)
+            } else {
+                String::new()
+            },
+            retry,
+            next
+        ),
+        Code::MinExpand(_) => format!(
+            "stack.push(({}, p, 0));
+                    pc = {};
+                    true",
+            retry, next
+        ),
+        Code::Optional(class) => format!(
+            "if let Some(c) = next_char(subj, p).filter(|&c| {}) {{
+                        stack.push(({}, p, 0));
+                        p += c.len_utf8();
+                    }}
+                    pc = {};
+                    true",
+            condition(class),
+            next,
+            next
+        ),
+        Code::Match if anchor_end => "p == subj.len() && return true".to_owned(),
+        Code::Match => "return true".to_owned(),
+    };
+    writeln!(w, "                    {}", body).unwrap();
+}
+
+/// Arm taking the next alternative of a loop at `pc`, resumed from the stack
+/// with `n` chars taken so far by a `MaxExpand`.
+fn retry_state(pc: usize, retry: usize, code: &Code) -> Option<String> {
+    let next = pc + 1;
+    match code {
+        Code::MaxExpand { min, .. } => Some(format!(
+            "n > {} && {{
+                        p -= subj[..p].chars().next_back().unwrap().len_utf8();
+                        stack.push(({}, p, n - 1));
+                        pc = {};
+                        true
+                    }}",
+            min, retry, next
+        )),
+        Code::MinExpand(class) => Some(format!(
+            "match next_char(subj, p) {{
+                        Some(c) if {} => {{
+                            p += c.len_utf8();
+                            stack.push(({}, p, 0));
+                            pc = {};
+                            true
+                        }}
+                        _ => false,
+                    }}",
+            condition(class),
+            retry,
+            next
+        )),
+        _ => None,
+    }
+}
+
+/// Boolean expression telling whether the char `c` is in the class,
+/// same as [`CharacterClass::is_matched`].
+fn condition(class: &CharacterClass) -> String {
+    let negated = |is_in: &bool, test: &str| {
+        if *is_in {
+            test.to_owned()
+        } else {
+            format!("!{}", test)
+        }
+    };
+    match class {
+        CharacterClass::Literal(c) => format!("c == {:?}", c),
+        CharacterClass::Any => "true".to_owned(),
+        CharacterClass::AlphaNumeric(is_in) => negated(is_in, "c.is_alphanumeric()"),
+        CharacterClass::Letter(is_in) => negated(is_in, "c.is_alphabetic()"),
+        CharacterClass::ControlChar(is_in) => negated(is_in, "c.is_ascii_control()"),
+        CharacterClass::Digit(is_in) => negated(is_in, "c.is_numeric()"),
+        CharacterClass::Printable(is_in) => negated(is_in, "(c.is_ascii_graphic() && c != ' ')"),
+        CharacterClass::Punctuation(is_in) => negated(is_in, "c.is_ascii_punctuation()"),
+        CharacterClass::WhiteSpace(is_in) => negated(is_in, "c.is_whitespace()"),
+        CharacterClass::Hexadecimal(is_in) => negated(is_in, "c.is_ascii_hexdigit()"),
+        CharacterClass::Lowercase(is_in) => format!(
+            "(c.is_alphabetic() && c.to_lowercase().next() {} Some(c))",
+            if *is_in { "==" } else { "!=" }
+        ),
+        CharacterClass::Uppercase(is_in) => format!(
+            "(c.is_alphabetic() && c.to_uppercase().next() {} Some(c))",
+            if *is_in { "==" } else { "!=" }
+        ),
+        CharacterClass::Range(r) => format!("({:?}..={:?}).contains(&c)", r.start(), r.end()),
+        CharacterClass::Set(s) => format!("({})", any_of(s)),
+        CharacterClass::Unset(s) => format!("!({})", any_of(s)),
+    }
+}
+
+fn any_of(items: &[CharacterClass]) -> String {
+    if items.is_empty() {
+        return "false".to_owned();
+    }
+    items.iter().map(condition).collect::<Vec<_>>().join(" || ")
+}
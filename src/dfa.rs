@@ -0,0 +1,513 @@
+use std::{collections::HashMap, ops::Range, sync::Mutex};
+
+use crate::bytecode::{character_class::CharacterClass, class_table::ClassTable, code::Code};
+
+pub(crate) const UNKNOWN: usize = usize::MAX;
+pub(crate) const MAX_STATES: usize = 4096;
+
+/// Lazy DFA for programs without captures-dependent codes (`%1`, `%b`, `%f`).
+///
+/// The NFA is the `Code` program itself: a thread is the pc of a `Char`
+/// waiting for the next character or the pc of `Match`. `Save`, `Jmp` and
+/// `Split` are followed while building the closures, so they cost nothing at
+/// match time. Characters are compressed into symbols: two characters are
+/// the same symbol when every class of the program agrees on them.
+#[derive(Debug)]
+pub(crate) struct Dfa {
+    classes: Box<[ClassTable]>,
+    class_of: Box<[usize]>,
+    first: Box<[usize]>,
+    follow: Box<[Box<[usize]>]>,
+    preds: Box<[Box<[usize]>]>,
+    last: Box<[usize]>,
+    match_pc: usize,
+    anchor_start: bool,
+    anchor_end: bool,
+    ascii_symbols: [usize; 128],
+    ascii_signatures: Box<[u64]>,
+    pool: Mutex<Vec<Cache>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    /// Threads after a `Match` are cut like a backtracking engine would never
+    /// try them. Used to find where the leftmost-first match ends.
+    LeftmostFirst,
+    /// Every thread runs to the end. Used for yes/no answers.
+    All,
+}
+
+#[derive(Debug)]
+struct State {
+    threads: Box<[usize]>,
+    restart: bool,
+    is_match: bool,
+    next: Vec<usize>,
+}
+
+#[derive(Debug, Default)]
+struct States {
+    states: Vec<State>,
+    map: HashMap<(Box<[usize]>, bool, bool), usize>,
+}
+
+#[derive(Debug)]
+struct Cache {
+    leftmost: States,
+    all: States,
+    reverse: States,
+    signatures: Vec<u64>,
+    symbols: HashMap<u64, usize>,
+    seen: Vec<bool>,
+}
+
+impl Dfa {
+    pub fn new(
+        program: &[Code],
+        tables: &[ClassTable],
+        anchor_start: bool,
+        anchor_end: bool,
+    ) -> Option<Self> {
+        let (program, tables) = &expand_chars(program, tables);
+        let mut classes: Vec<ClassTable> = vec![];
+        let mut class_of = vec![UNKNOWN; 2 * program.len()];
+        let mut match_pc = None;
+        for (pc, code) in program.iter().enumerate() {
+            match code {
+                Code::Char(_)
+                | Code::MaxExpand { min: 0 | 1, .. }
+                | Code::MinExpand(_)
+                | Code::Optional(_) => {
+                    let table = &tables[pc];
+                    let class = classes.iter().position(|x| x == table).unwrap_or_else(|| {
+                        classes.push(table.clone());
+                        classes.len() - 1
+                    });
+                    class_of[pc] = class;
+                    class_of[looping(program, pc)] = class;
+                }
+                Code::Match => match_pc = Some(pc),
+                Code::Jmp(_) | Code::Split { .. } | Code::Save(_) => {}
+                Code::Chars(_)
+                | Code::Captured(_)
+                | Code::Border(_, _)
+                | Code::Frontier(_)
+                | Code::MaxExpand { .. } => return None,
+            }
+        }
+        if classes.len() > u64::BITS as usize {
+            return None;
+        }
+        let match_pc = match_pc?;
+
+        let first = closure(program, 0);
+        let mut follow = vec![Box::default(); class_of.len()];
+        let mut preds = vec![vec![]; class_of.len()];
+        for (pc, code) in program.iter().enumerate() {
+            let nodes = match code {
+                Code::Char(_) | Code::Optional(_) => [(pc, closure(program, pc + 1))].to_vec(),
+                Code::MaxExpand { .. } | Code::MinExpand(_) => [
+                    (pc, loop_closure(program, pc)),
+                    (looping(program, pc), loop_closure(program, pc)),
+                ]
+                .to_vec(),
+                _ => vec![],
+            };
+            for (node, next) in nodes {
+                for &thread in next.iter() {
+                    if thread != match_pc {
+                        preds[thread].push(node);
+                    }
+                }
+                follow[node] = next;
+            }
+        }
+        for node_preds in preds.iter_mut() {
+            node_preds.sort_unstable();
+            node_preds.dedup();
+        }
+        let last = (0..class_of.len())
+            .filter(|&node| follow[node].contains(&match_pc))
+            .collect();
+
+        let mut dfa = Self {
+            classes: classes.into(),
+            class_of: class_of.into(),
+            first,
+            follow: follow.into(),
+            preds: preds.into_iter().map(Vec::into_boxed_slice).collect(),
+            last,
+            match_pc,
+            anchor_start,
+            anchor_end,
+            ascii_symbols: [0; 128],
+            ascii_signatures: Box::default(),
+            pool: Mutex::new(vec![]),
+        };
+        let mut signatures = vec![];
+        for c in 0..128u8 {
+            let signature = dfa.signature(c as char);
+            dfa.ascii_symbols[c as usize] = signatures
+                .iter()
+                .position(|&s| s == signature)
+                .unwrap_or_else(|| {
+                    signatures.push(signature);
+                    signatures.len() - 1
+                });
+        }
+        dfa.ascii_signatures = signatures.into();
+        Some(dfa)
+    }
+
+    pub fn is_match(&self, subj: &str) -> bool {
+        self.with_cache(|cache| {
+            let mut state = cache.start(self, Mode::All);
+            for c in subj.chars() {
+                if cache.all.states[state].is_match && !self.anchor_end {
+                    return true;
+                }
+                if cache.all.is_dead(state) {
+                    return false;
+                }
+                state = cache.next(self, Mode::All, state, c);
+            }
+            cache.all.states[state].is_match
+        })
+    }
+
+    pub fn find(&self, subj: &str) -> Option<Range<usize>> {
+        let end = if self.anchor_end {
+            self.is_match(subj).then_some(subj.len())?
+        } else {
+            self.find_end(subj)?
+        };
+        let start = if self.anchor_start {
+            0
+        } else {
+            self.find_start(&subj[..end])
+        };
+        Some(start..end)
+    }
+}
+
+impl Dfa {
+    fn with_cache<T>(&self, f: impl FnOnce(&mut Cache) -> T) -> T {
+        let cache = self.pool.lock().unwrap().pop();
+        let mut cache = cache.unwrap_or_else(|| Cache::new(self));
+        let result = f(&mut cache);
+        self.pool.lock().unwrap().push(cache);
+        result
+    }
+
+    fn signature(&self, c: char) -> u64 {
+        self.classes
+            .iter()
+            .enumerate()
+            .filter(|(_, class)| class.is_matched(c))
+            .fold(0, |signature, (n, _)| signature | 1 << n)
+    }
+
+    /// End of the match a backtracking engine would find first.
+    fn find_end(&self, subj: &str) -> Option<usize> {
+        self.with_cache(|cache| {
+            let mut state = cache.start(self, Mode::LeftmostFirst);
+            let mut end = cache.leftmost.states[state].is_match.then_some(0);
+            for (i, c) in subj.char_indices() {
+                if cache.leftmost.is_dead(state) {
+                    break;
+                }
+                state = cache.next(self, Mode::LeftmostFirst, state, c);
+                if cache.leftmost.states[state].is_match {
+                    end = Some(i + c.len_utf8());
+                }
+            }
+            end
+        })
+    }
+
+    /// Smallest start of a match ending exactly at the end of `subj`,
+    /// `subj.len()` when there is none. Walks the program backwards from its `Match`.
+    pub fn find_start(&self, subj: &str) -> usize {
+        self.with_cache(|cache| {
+            let mut state = cache.reverse_start(self);
+            let mut start = subj.len();
+            for (i, c) in subj.char_indices().rev() {
+                if cache.reverse.is_dead(state) {
+                    break;
+                }
+                state = cache.reverse_next(self, state, c);
+                if cache.reverse.states[state].is_match {
+                    start = i;
+                }
+            }
+            start
+        })
+    }
+}
+
+impl States {
+    fn is_dead(&self, state: usize) -> bool {
+        let state = &self.states[state];
+        state.threads.is_empty() && !state.restart
+    }
+
+    fn insert(&mut self, threads: Box<[usize]>, restart: bool, is_match: bool) -> usize {
+        if let Some(&id) = self.map.get(&(threads.clone(), restart, is_match)) {
+            return id;
+        }
+        let id = self.states.len();
+        self.map.insert((threads.clone(), restart, is_match), id);
+        self.states.push(State {
+            threads,
+            restart,
+            is_match,
+            next: vec![],
+        });
+        id
+    }
+
+    /// Drops every state but `state` once the cache is full, returns the new id of `state`.
+    fn shrink(&mut self, state: usize) -> usize {
+        if self.states.len() < MAX_STATES {
+            return state;
+        }
+        let State {
+            threads,
+            restart,
+            is_match,
+            ..
+        } = self.states.swap_remove(state);
+        self.states.clear();
+        self.map.clear();
+        self.insert(threads, restart, is_match)
+    }
+
+    fn cached(&self, state: usize, symbol: usize) -> Option<usize> {
+        self.states[state]
+            .next
+            .get(symbol)
+            .copied()
+            .filter(|&next| next != UNKNOWN)
+    }
+
+    fn remember(&mut self, state: usize, symbol: usize, next: usize) {
+        let transitions = &mut self.states[state].next;
+        if transitions.len() <= symbol {
+            transitions.resize(symbol + 1, UNKNOWN);
+        }
+        transitions[symbol] = next;
+    }
+}
+
+impl Cache {
+    fn new(dfa: &Dfa) -> Self {
+        Self {
+            leftmost: States::default(),
+            all: States::default(),
+            reverse: States::default(),
+            signatures: dfa.ascii_signatures.to_vec(),
+            symbols: HashMap::new(),
+            seen: vec![false; dfa.class_of.len()],
+        }
+    }
+
+    fn states(&mut self, mode: Mode) -> &mut States {
+        match mode {
+            Mode::LeftmostFirst => &mut self.leftmost,
+            Mode::All => &mut self.all,
+        }
+    }
+
+    fn symbol(&mut self, dfa: &Dfa, c: char) -> usize {
+        if c.is_ascii() {
+            return dfa.ascii_symbols[c as usize];
+        }
+        let signature = dfa.signature(c);
+        let signatures = &mut self.signatures;
+        *self.symbols.entry(signature).or_insert_with(|| {
+            signatures.push(signature);
+            signatures.len() - 1
+        })
+    }
+
+    fn start(&mut self, dfa: &Dfa, mode: Mode) -> usize {
+        let mut threads = vec![];
+        self.push_threads(&mut threads, &dfa.first);
+        self.finish(dfa, mode, threads, !dfa.anchor_start)
+    }
+
+    fn next(&mut self, dfa: &Dfa, mode: Mode, state: usize, c: char) -> usize {
+        let symbol = self.symbol(dfa, c);
+        if let Some(next) = self.states(mode).cached(state, symbol) {
+            return next;
+        }
+        let state = self.states(mode).shrink(state);
+        let signature = self.signatures[symbol];
+        let current = &self.states(mode).states[state];
+        let (threads, restart) = (current.threads.clone(), current.restart);
+        let mut next_threads = vec![];
+        let mut matched = false;
+        for &pc in threads.iter() {
+            if pc == dfa.match_pc {
+                matched = true;
+                if mode == Mode::LeftmostFirst {
+                    break;
+                }
+            } else if signature & 1 << dfa.class_of[pc] != 0 {
+                self.push_threads(&mut next_threads, &dfa.follow[pc]);
+            }
+        }
+        let restart = restart && !(matched && mode == Mode::LeftmostFirst);
+        let next = self.finish(dfa, mode, next_threads, restart);
+        self.states(mode).remember(state, symbol, next);
+        next
+    }
+
+    fn finish(&mut self, dfa: &Dfa, mode: Mode, mut threads: Vec<usize>, restart: bool) -> usize {
+        let matched = threads.contains(&dfa.match_pc);
+        if restart && !(matched && mode == Mode::LeftmostFirst) {
+            self.push_threads(&mut threads, &dfa.first);
+        }
+        for &pc in threads.iter() {
+            self.seen[pc] = false;
+        }
+        if mode == Mode::All {
+            threads.sort_unstable();
+        }
+        let is_match = threads.contains(&dfa.match_pc);
+        self.states(mode).insert(threads.into(), restart, is_match)
+    }
+
+    fn push_threads(&mut self, threads: &mut Vec<usize>, pcs: &[usize]) {
+        for &pc in pcs {
+            if !self.seen[pc] {
+                self.seen[pc] = true;
+                threads.push(pc);
+            }
+        }
+    }
+
+    /// Reverse states hold the `Char`s which may read the previous character.
+    fn reverse_start(&mut self, dfa: &Dfa) -> usize {
+        let nullable = dfa.first.contains(&dfa.match_pc);
+        self.reverse.insert(dfa.last.clone(), false, nullable)
+    }
+
+    fn reverse_next(&mut self, dfa: &Dfa, state: usize, c: char) -> usize {
+        let symbol = self.symbol(dfa, c);
+        if let Some(next) = self.reverse.cached(state, symbol) {
+            return next;
+        }
+        let state = self.reverse.shrink(state);
+        let signature = self.signatures[symbol];
+        let mut is_match = false;
+        let mut threads = vec![];
+        for &pc in self.reverse.states[state].threads.iter() {
+            if signature & 1 << dfa.class_of[pc] != 0 {
+                is_match |= dfa.first.contains(&pc);
+                threads.extend_from_slice(&dfa.preds[pc]);
+            }
+        }
+        threads.sort_unstable();
+        threads.dedup();
+        let next = self.reverse.insert(threads.into(), false, is_match);
+        self.reverse.remember(state, symbol, next);
+        next
+    }
+}
+
+/// Spells every `Chars` out as a sequence of `Char`s, so that each thread reads one char.
+pub(crate) fn expand_chars(
+    program: &[Code],
+    tables: &[ClassTable],
+) -> (Vec<Code>, Vec<ClassTable>) {
+    let mut renumbered = Vec::with_capacity(program.len() + 1);
+    let mut next = 0;
+    for code in program {
+        renumbered.push(next);
+        next += match code {
+            Code::Chars(s) => s.len(),
+            _ => 1,
+        };
+    }
+    renumbered.push(next);
+    let mut expanded = Vec::with_capacity(next);
+    let mut expanded_tables = Vec::with_capacity(next);
+    for (code, table) in program.iter().zip(tables) {
+        match code {
+            Code::Chars(s) => {
+                for c in s.iter() {
+                    let class = CharacterClass::Literal(*c);
+                    expanded_tables.push(ClassTable::new(&class));
+                    expanded.push(Code::Char(class));
+                }
+                continue;
+            }
+            Code::Jmp(x) => expanded.push(Code::Jmp(renumbered[*x])),
+            Code::Split { x, y } => expanded.push(Code::Split {
+                x: renumbered[*x],
+                y: renumbered[*y],
+            }),
+            code => expanded.push(code.clone()),
+        }
+        expanded_tables.push(table.clone());
+    }
+    (expanded, expanded_tables)
+}
+
+/// Thread of a `MaxExpand` or `MinExpand` which has already taken a char.
+pub(crate) fn looping(program: &[Code], pc: usize) -> usize {
+    program.len() + pc
+}
+
+/// Threads reachable from `pc` without reading a character, in priority order.
+pub(crate) fn closure(program: &[Code], pc: usize) -> Box<[usize]> {
+    fn visit(program: &[Code], pc: usize, visited: &mut [bool], threads: &mut Vec<usize>) {
+        if visited[pc] {
+            return;
+        }
+        visited[pc] = true;
+        match &program[pc] {
+            Code::Jmp(x) => visit(program, *x, visited, threads),
+            Code::Split { x, y } => {
+                visit(program, *x, visited, threads);
+                visit(program, *y, visited, threads);
+            }
+            Code::Save(_) => visit(program, pc + 1, visited, threads),
+            Code::MaxExpand { min: 0, .. } => {
+                threads.push(looping(program, pc));
+                visit(program, pc + 1, visited, threads);
+            }
+            Code::MinExpand(_) => {
+                visit(program, pc + 1, visited, threads);
+                threads.push(looping(program, pc));
+            }
+            Code::Optional(_) => {
+                threads.push(pc);
+                visit(program, pc + 1, visited, threads);
+            }
+            _ => threads.push(pc),
+        }
+    }
+    let mut threads = vec![];
+    visit(program, pc, &mut vec![false; program.len()], &mut threads);
+    let mut seen = vec![false; 2 * program.len()];
+    threads.retain(|&thread| !std::mem::replace(&mut seen[thread], true));
+    threads.into()
+}
+
+/// Threads after a `MaxExpand` or `MinExpand` at `pc` has taken a char.
+pub(crate) fn loop_closure(program: &[Code], pc: usize) -> Box<[usize]> {
+    let next = closure(program, pc + 1);
+    let this = [looping(program, pc)];
+    let threads = if let Code::MinExpand(_) = program[pc] {
+        [next.as_ref(), &this].concat()
+    } else {
+        [&this, next.as_ref()].concat()
+    };
+    let mut seen = vec![false; 2 * program.len()];
+    threads
+        .into_iter()
+        .filter(|&thread| !std::mem::replace(&mut seen[thread], true))
+        .collect()
+}
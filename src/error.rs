@@ -0,0 +1,123 @@
+use std::fmt;
+
+/// Malformed pattern or replacement string. `position` is the byte offset
+/// in it where the offending item starts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub position: usize,
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    TooManyCaptures,
+    UnmatchedClose,
+    UnclosedCapture,
+    NoSuchCapture(usize),
+    EndsWithPercent,
+    IllegalEscape(char),
+    MissingBracket,
+    BorderChars,
+    FrontierWithoutSet,
+    /// `%` in a replacement string followed by neither a digit nor `%`.
+    BadReplacement,
+}
+
+impl Error {
+    pub fn new(position: usize, kind: ErrorKind) -> Self {
+        Self { position, kind }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ErrorKind::TooManyCaptures => write!(f, "Too many captures")?,
+            ErrorKind::UnmatchedClose => write!(f, "Unmatched ')'")?,
+            ErrorKind::UnclosedCapture => write!(f, "Unfinished capture")?,
+            ErrorKind::NoSuchCapture(n) => write!(f, "You have no such capture {}", n)?,
+            ErrorKind::EndsWithPercent => write!(f, "Pattern ends with '%'")?,
+            ErrorKind::IllegalEscape(c) => write!(f, "Illegal char {:?} in escaping", c)?,
+            ErrorKind::MissingBracket => write!(f, "Missing ']'")?,
+            ErrorKind::BorderChars => write!(f, "%b needs two different chars")?,
+            ErrorKind::FrontierWithoutSet => write!(f, "After %f must be '['")?,
+            ErrorKind::BadReplacement => write!(f, "Invalid use of '%' in replacement string")?,
+        }
+        write!(f, " at {}.", self.position)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Bytes which are not a program written by [`crate::regex::Regex::to_bytes`],
+/// or a program which is not safe to run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    BadHeader,
+    UnsupportedVersion(u8),
+    Truncated,
+    TrailingBytes,
+    BadTag(u8),
+    BadChar(u32),
+    TooDeep,
+    JumpOutOfRange { pc: usize, target: usize },
+    BadSaveSlot { pc: usize, slot: usize },
+    NoSuchCapture { pc: usize, capture: usize },
+    FallsOffEnd { pc: usize },
+    EmptyLoop { pc: usize },
+    UnsavedSlot { pc: usize, slot: usize },
+    ReopenedCapture { pc: usize, slot: usize },
+    NoMatch,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::BadHeader => write!(f, "Not a compiled pattern."),
+            DecodeError::UnsupportedVersion(v) => write!(f, "Unsupported format version {}.", v),
+            DecodeError::Truncated => write!(f, "Unexpected end of data."),
+            DecodeError::TrailingBytes => write!(f, "Unexpected data after the program."),
+            DecodeError::BadTag(tag) => write!(f, "Unknown tag {}.", tag),
+            DecodeError::BadChar(c) => write!(f, "Invalid char {:#x}.", c),
+            DecodeError::TooDeep => write!(f, "Character sets are nested too deep."),
+            DecodeError::JumpOutOfRange { pc, target } => {
+                write!(f, "Jump to {} at {} is out of the program.", target, pc)
+            }
+            DecodeError::BadSaveSlot { pc, slot } => write!(f, "No save slot {} at {}.", slot, pc),
+            DecodeError::NoSuchCapture { pc, capture } => {
+                write!(f, "No capture {} for back-reference at {}.", capture, pc)
+            }
+            DecodeError::FallsOffEnd { pc } => write!(f, "Program falls off its end at {}.", pc),
+            DecodeError::EmptyLoop { pc } => {
+                write!(f, "Loop through {} may not consume input.", pc)
+            }
+            DecodeError::UnsavedSlot { pc, slot } => {
+                write!(f, "Slot {} may be unsaved at {}.", slot, pc)
+            }
+            DecodeError::ReopenedCapture { pc, slot } => {
+                write!(
+                    f,
+                    "Slot {} may be saved after its capture closed at {}.",
+                    slot, pc
+                )
+            }
+            DecodeError::NoMatch => write!(f, "Program does not end with Match."),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Input no rule of a [`crate::tokenizer::Tokenizer`] matches, `offset` is in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenizeError {
+    pub offset: usize,
+}
+
+impl fmt::Display for TokenizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "No token matches at {}.", self.offset)
+    }
+}
+
+impl std::error::Error for TokenizeError {}
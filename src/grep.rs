@@ -0,0 +1,249 @@
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead},
+    ops::Range,
+};
+
+use crate::{
+    regex::{Regex, Scratch},
+    split::Matches,
+    Match,
+};
+
+/// A line with matches or, with context lines asked for, one next to such a line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    /// Counted from 1.
+    pub number: usize,
+    /// Byte offset of the line in the input.
+    pub offset: usize,
+    /// The line without its `\n` or `\r\n`.
+    pub text: String,
+    /// Matches in the line found the way [`Regex::split`] finds them, none for a context line.
+    pub matches: Box<[LineMatch]>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineMatch {
+    /// Byte ranges of the captures in the line.
+    pub captures: Box<[Range<usize>]>,
+    /// The same ranges counted in chars from the start of the line.
+    pub columns: Box<[Range<usize>]>,
+    /// Same as [`Match::position_captures`].
+    pub position_captures: u16,
+}
+
+impl Line {
+    pub fn is_context(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    /// The `n`th match in the line.
+    pub fn get_match(&self, n: usize) -> Option<Match<'_>> {
+        self.matches.get(n).map(|m| Match {
+            subj: &self.text,
+            captures: m.captures.clone(),
+            position_captures: m.position_captures,
+        })
+    }
+}
+
+/// Runs a pattern over every line of a [`BufRead`] like `grep -n`, yielding the
+/// lines with matches and, if asked for, the lines before and after them.
+/// One [`Scratch`] is reused for all lines.
+#[derive(Debug)]
+pub struct Grep<'r, R> {
+    regex: &'r Regex,
+    reader: R,
+    before: usize,
+    after: usize,
+    scratch: Scratch,
+    /// Lines read and not yielded, at most `before` of them.
+    previous: VecDeque<Line>,
+    ready: VecDeque<Line>,
+    /// Context lines still to yield after the last line with matches.
+    trailing: usize,
+    number: usize,
+    offset: usize,
+    done: bool,
+}
+
+impl<'r, R: BufRead> Grep<'r, R> {
+    pub fn new(regex: &'r Regex, reader: R) -> Self {
+        Self::with_context(regex, reader, 0, 0)
+    }
+
+    /// Same as [`Grep::new`] but also yields up to `before` lines before and
+    /// `after` lines after every line with matches, like `grep -B before -A after`.
+    pub fn with_context(regex: &'r Regex, reader: R, before: usize, after: usize) -> Self {
+        Self {
+            regex,
+            reader,
+            before,
+            after,
+            scratch: Scratch::default(),
+            previous: VecDeque::new(),
+            ready: VecDeque::new(),
+            trailing: 0,
+            number: 0,
+            offset: 0,
+            done: false,
+        }
+    }
+
+    /// Reads one line and queues whatever it makes ready. Returns `false` at the end.
+    fn read_line(&mut self) -> io::Result<bool> {
+        let mut text = String::new();
+        let read = self.reader.read_line(&mut text)?;
+        if read == 0 {
+            return Ok(false);
+        }
+        self.number += 1;
+        let offset = self.offset;
+        self.offset += read;
+        if text.ends_with('\n') {
+            text.pop();
+            if text.ends_with('\r') {
+                text.pop();
+            }
+        }
+
+        let mut matches =
+            Matches::with_scratch(self.regex, &text, std::mem::take(&mut self.scratch));
+        // Matches come in order, so one cursor walks the line once for all of them.
+        let (mut byte, mut column) = (0, 0);
+        let found = matches
+            .by_ref()
+            .map(|m| {
+                let start = m.captures[0].start;
+                column += text[byte..start].chars().count();
+                byte = start;
+                let column_of = |at: usize| column + text[start..at].chars().count();
+                LineMatch {
+                    columns: m
+                        .captures
+                        .iter()
+                        .map(|r| column_of(r.start)..column_of(r.end))
+                        .collect(),
+                    captures: m.captures,
+                    position_captures: m.position_captures,
+                }
+            })
+            .collect::<Box<[_]>>();
+        self.scratch = matches.into_scratch();
+
+        let line = Line {
+            number: self.number,
+            offset,
+            text,
+            matches: found,
+        };
+        if !line.is_context() {
+            self.ready.extend(self.previous.drain(..));
+            self.ready.push_back(line);
+            self.trailing = self.after;
+        } else if self.trailing > 0 {
+            self.trailing -= 1;
+            self.ready.push_back(line);
+        } else if self.before > 0 {
+            if self.previous.len() == self.before {
+                self.previous.pop_front();
+            }
+            self.previous.push_back(line);
+        }
+        Ok(true)
+    }
+}
+
+impl<R: BufRead> Iterator for Grep<'_, R> {
+    type Item = io::Result<Line>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.ready.pop_front() {
+                return Some(Ok(line));
+            }
+            if self.done {
+                return None;
+            }
+            match self.read_line() {
+                Ok(true) => {}
+                Ok(false) => self.done = true,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Grep;
+    use crate::regex::compile;
+
+    #[test]
+    fn numbers_columns_and_context() {
+        let text = "local x = 1\r\n-- жж TODO: a\nreturn x\n\nend\nTODO: b\nTODO: c";
+        let todo = compile("TODO: ?(%a)");
+        let lines = Grep::new(&todo, text.as_bytes())
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![(2, 13), (6, 43), (7, 51)],
+            lines
+                .iter()
+                .map(|line| (line.number, line.offset))
+                .collect::<Vec<_>>()
+        );
+        let first = &lines[0].matches[0];
+        assert_eq!(vec![8..15, 14..15], first.captures.to_vec());
+        assert_eq!(vec![6..13, 12..13], first.columns.to_vec());
+        assert_eq!(
+            vec!["TODO: a", "a"],
+            lines[0].get_match(0).unwrap().captured_str().to_vec()
+        );
+
+        let context = Grep::with_context(&todo, text.as_bytes(), 1, 1)
+            .map(|line| {
+                let line = line.unwrap();
+                (line.number, line.is_context())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (1, true),
+                (2, false),
+                (3, true),
+                (5, true),
+                (6, false),
+                (7, false)
+            ],
+            context
+        );
+
+        let words = Grep::new(&compile("%a+"), "жы ab\n".as_bytes())
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            vec![vec![0..4], vec![5..7]],
+            words
+                .matches
+                .iter()
+                .map(|m| m.captures.to_vec())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(vec![3..5], words.matches[1].columns.to_vec());
+        assert!(todo.grep("").next().is_none());
+
+        let long = "жa ".repeat(20_000);
+        let line = compile("(ж)(a)").grep(&long).next().unwrap().unwrap();
+        assert_eq!(20_000, line.matches.len());
+        assert_eq!(
+            vec![59_997..59_999, 59_997..59_998, 59_998..59_999],
+            line.matches[19_999].columns.to_vec()
+        );
+    }
+}
@@ -1,15 +1,24 @@
 #[derive(Debug, PartialEq)]
 pub(crate) struct Input<'a> {
     pub subj: &'a str,
-    chars: Box<[(usize, char)]>,
+    chars: Vec<(usize, char)>,
 }
 
 impl<'a> Input<'a> {
-    pub fn new(subj: &'a str) -> Self {
-        Self {
-            subj,
-            chars: subj.char_indices().collect(),
-        }
+    pub fn with_buffer(subj: &'a str, mut chars: Vec<(usize, char)>) -> Self {
+        chars.clear();
+        chars.extend(subj.char_indices());
+        Self { subj, chars }
+    }
+
+    /// `chars` must be `subj.char_indices()`, e.g. the buffer of an earlier
+    /// `Input` on the same subject.
+    pub fn decoded(subj: &'a str, chars: Vec<(usize, char)>) -> Self {
+        Self { subj, chars }
+    }
+
+    pub fn into_buffer(self) -> Vec<(usize, char)> {
+        self.chars
     }
 
     pub fn len(&self) -> usize {
@@ -22,9 +31,25 @@ impl<'a> Input<'a> {
 
     pub fn get_byte_index(&self, char_index: usize) -> Option<usize> {
         if char_index == self.len() {
-            self.chars.last().map(|(i, c)| *i + c.len_utf8())
+            Some(self.subj.len())
         } else {
             self.chars.get(char_index).map(|(i, _)| *i)
         }
     }
+
+    /// Index of the char starting at byte `byte_index`, `None` inside a char.
+    pub fn get_char_index(&self, byte_index: usize) -> Option<usize> {
+        if byte_index == self.subj.len() {
+            return Some(self.len());
+        }
+        self.chars
+            .binary_search_by_key(&byte_index, |(i, _)| *i)
+            .ok()
+    }
+
+    /// Drops every char from char `len` on, as if the subject ended there.
+    pub fn truncate(&mut self, len: usize) {
+        self.subj = &self.subj[..self.get_byte_index(len).unwrap()];
+        self.chars.truncate(len);
+    }
 }
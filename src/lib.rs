@@ -1,14 +1,39 @@
 use std::ops::Range;
 
+use error::{Error, ErrorKind};
+
 mod bytecode;
+pub mod cache;
+pub mod codegen;
+mod dfa;
+pub mod error;
+pub mod grep;
 mod input;
+pub mod overlapping;
 mod recursive;
 pub mod regex;
+pub mod scanner;
+pub mod set;
+pub mod split;
+pub mod stream;
+pub mod tokenizer;
 
 #[derive(Debug)]
 pub struct Match<'a> {
     pub subj: &'a str,
     pub captures: Box<[Range<usize>]>,
+    /// Bit `n` is set when capture `n` is a position capture `()`.
+    pub position_captures: u16,
+}
+
+/// Position in a text as editors count it. Everything is counted from 0.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LineColumn {
+    pub line: usize,
+    /// Chars from the start of the line.
+    pub column: usize,
+    /// UTF-16 code units from the start of the line, the `character` of an LSP `Position`.
+    pub utf16_column: usize,
 }
 
 #[derive(Debug)]
@@ -18,9 +43,9 @@ pub enum Capture<'a> {
 }
 
 impl Match<'_> {
-    pub fn capture(&self, n: usize) -> Option<Capture> {
+    pub fn capture(&self, n: usize) -> Option<Capture<'_>> {
         self.captures.get(n).map(|r| {
-            if r.is_empty() {
+            if self.position_captures & 1 << n != 0 {
                 Capture::Index(r.start)
             } else {
                 Capture::Value(&self.subj[r.to_owned()])
@@ -28,16 +53,112 @@ impl Match<'_> {
         })
     }
 
-    pub fn captures(&self) -> Box<[Capture]> {
+    pub fn captures(&self) -> Box<[Capture<'_>]> {
         self.captures_iter().collect()
     }
 
-    pub fn captures_iter(&self) -> impl Iterator<Item = Capture> {
+    pub fn captures_iter(&self) -> impl Iterator<Item = Capture<'_>> {
         (0..self.captures.len())
             .map(|n| self.capture(n))
             .map(Option::unwrap)
     }
 
+    /// Appends `repl` to `dst` with `%0`-`%9` replaced by captures and `%%` by `%`,
+    /// the way `string.gsub` does. Without captures in the pattern `%1` is the whole match.
+    ///
+    /// Panics on a `%` followed by anything else or a capture the pattern does not
+    /// have, see [`Match::try_expand`].
+    pub fn expand(&self, repl: &str, dst: &mut String) {
+        self.try_expand(repl, dst)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as [`Match::expand`] but reports a malformed `repl`, with the position
+    /// of its bad `%`, instead of panicking. `dst` is left as it was on error.
+    pub fn try_expand(&self, repl: &str, dst: &mut String) -> Result<(), Error> {
+        let len = dst.len();
+        self.expand_into(repl, dst)
+            .inspect_err(|_| dst.truncate(len))
+    }
+
+    fn expand_into(&self, repl: &str, dst: &mut String) -> Result<(), Error> {
+        let mut chars = repl.char_indices();
+        while let Some((position, c)) = chars.next() {
+            if c != '%' {
+                dst.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some((_, '%')) => dst.push('%'),
+                Some((_, d @ '0'..='9')) => {
+                    let mut n = d.to_digit(10).unwrap() as usize;
+                    if n == 1 && self.captures.len() == 1 {
+                        n = 0;
+                    }
+                    match self.capture(n) {
+                        _ if n == 0 => dst.push_str(&self.subj[self.captures[0].clone()]),
+                        Some(Capture::Value(s)) => dst.push_str(s),
+                        Some(Capture::Index(i)) => dst.push_str(&(i + 1).to_string()),
+                        None => return Err(Error::new(position, ErrorKind::NoSuchCapture(n))),
+                    }
+                }
+                _ => return Err(Error::new(position, ErrorKind::BadReplacement)),
+            }
+        }
+        Ok(())
+    }
+
+    /// The capture ranges counted in chars instead of bytes.
+    pub fn char_ranges(&self) -> Box<[Range<usize>]> {
+        self.positions(0, |n, _| n + 1)
+    }
+
+    /// The capture ranges counted in UTF-16 code units instead of bytes.
+    pub fn utf16_ranges(&self) -> Box<[Range<usize>]> {
+        self.positions(0, |n, c| n + c.len_utf16())
+    }
+
+    /// The capture ranges as lines and columns of the subject.
+    pub fn line_columns(&self) -> Box<[Range<LineColumn>]> {
+        self.positions(LineColumn::default(), |p, c| match c {
+            '\n' => LineColumn {
+                line: p.line + 1,
+                ..LineColumn::default()
+            },
+            c => LineColumn {
+                column: p.column + 1,
+                utf16_column: p.utf16_column + c.len_utf16(),
+                ..p
+            },
+        })
+    }
+
+    /// Maps every capture bound to a position which `step` advances over each
+    /// char, in one pass over the subject up to the last bound.
+    fn positions<P: Copy>(&self, start: P, step: impl Fn(P, char) -> P) -> Box<[Range<P>]> {
+        let mut bounds = self
+            .captures
+            .iter()
+            .flat_map(|r| [r.start, r.end])
+            .collect::<Vec<_>>();
+        bounds.sort_unstable();
+        bounds.dedup();
+        let mut positions = Vec::with_capacity(bounds.len());
+        let mut chars = self.subj.char_indices().peekable();
+        let mut position = start;
+        for &bound in &bounds {
+            while let Some((_, c)) = chars.next_if(|&(i, _)| i < bound) {
+                position = step(position, c);
+            }
+            positions.push(position);
+        }
+        let at = |bound| positions[bounds.binary_search(&bound).unwrap()];
+        self.captures
+            .iter()
+            .map(|r| at(r.start)..at(r.end))
+            .collect()
+    }
+
     pub fn captured_str(&self) -> Box<[&str]> {
         self.captures_iter()
             .filter_map(|capture| {
@@ -50,3 +171,41 @@ impl Match<'_> {
             .collect()
     }
 }
+
+/// Reusable storage for the capture ranges of a match, see
+/// [`regex::Regex::captures_read`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Captures {
+    ranges: Vec<Range<usize>>,
+    position_captures: u16,
+}
+
+impl Captures {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn get(&self, n: usize) -> Option<Range<usize>> {
+        self.ranges.get(n).cloned()
+    }
+
+    pub fn as_slice(&self) -> &[Range<usize>] {
+        &self.ranges
+    }
+
+    pub fn to_match<'a>(&self, subj: &'a str) -> Match<'a> {
+        Match {
+            subj,
+            captures: self.ranges.as_slice().into(),
+            position_captures: self.position_captures,
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use crate::{
+    bytecode::context::{Context, Scratch},
+    regex::Regex,
+    Match,
+};
+
+/// A match attempt at every `stride`-th char of a subject, see [`Regex::overlapping_matches`].
+#[derive(Debug)]
+pub struct OverlappingMatches<'a> {
+    regex: &'a Regex,
+    ctx: Context<'a>,
+    /// Char index of the next attempt.
+    position: usize,
+    stride: usize,
+}
+
+impl<'a> OverlappingMatches<'a> {
+    pub(crate) fn new(regex: &'a Regex, subj: &'a str, stride: usize) -> Self {
+        assert!(stride > 0, "The stride must be positive.");
+        Self {
+            regex,
+            ctx: regex.context(subj, Scratch::default()),
+            position: 0,
+            stride,
+        }
+    }
+}
+
+impl<'a> Iterator for OverlappingMatches<'a> {
+    type Item = Match<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let last = if self.regex.program.anchor_start {
+            0
+        } else {
+            self.ctx.input.len()
+        };
+        while self.position <= last {
+            let start = self.position;
+            self.position += self.stride;
+            if self.regex.match_here(&mut self.ctx, start) {
+                let mut ranges = vec![];
+                self.ctx.read_captured_ranges(&mut ranges);
+                return Some(Match {
+                    subj: self.ctx.input.subj,
+                    captures: ranges.into(),
+                    position_captures: self.regex.program.position_captures,
+                });
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::OverlappingMatches;
+    use crate::regex::compile;
+
+    fn all(matches: OverlappingMatches<'_>) -> Vec<&str> {
+        matches.map(|m| &m.subj[m.captures[0].clone()]).collect()
+    }
+
+    #[test]
+    fn attempts_every_position() {
+        let bigram = compile("%a%a");
+        assert_eq!(vec!["ab", "bc"], all(bigram.overlapping_matches("abc")));
+        assert_eq!(
+            vec!["жы", "ыш", "ша"],
+            all(bigram.overlapping_matches("жыша"))
+        );
+        assert_eq!(
+            vec!["ab", "cd"],
+            all(bigram.overlapping_matches_with_stride("abcde", 2))
+        );
+        assert_eq!(
+            vec!["", "", ""],
+            all(compile("%d*").overlapping_matches("ab"))
+        );
+        assert_eq!(vec!["a"], all(compile("^%a").overlapping_matches("ab")));
+        assert_eq!(vec!["b"], all(compile("%a$").overlapping_matches("ab")));
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_stride() {
+        compile("a").overlapping_matches_with_stride("a", 0);
+    }
+}
@@ -4,21 +4,35 @@ use crate::bytecode::context::Context;
 pub(crate) fn exec(ctx: &mut Context) -> bool {
     loop {
         match &ctx.program[ctx.program_counter] {
-            Code::Char(c) => {
+            Code::Char(_) => {
+                let class = &ctx.tables[ctx.program_counter];
                 let other = ctx.input.get_char(ctx.subj_pointer).copied();
-                if other.is_some() && c.is_matched(other.unwrap()) {
+                if other.is_some() && class.is_matched(other.unwrap()) {
                     ctx.program_counter += 1;
                     ctx.subj_pointer += 1;
                 } else {
+                    ctx.hit_end |= other.is_none();
                     return false;
                 }
             }
+            Code::Chars(s) => {
+                for (i, c) in s.iter().enumerate() {
+                    let other = ctx.input.get_char(ctx.subj_pointer + i);
+                    if other != Some(c) {
+                        ctx.hit_end |= other.is_none();
+                        return false;
+                    }
+                }
+                ctx.subj_pointer += s.len();
+                ctx.program_counter += 1;
+            }
             Code::Captured(n) => {
                 let old = ctx.subj_pointer;
                 for captured_index in ctx.saved_range(*n) {
                     if ctx.input.get_char(ctx.subj_pointer) == ctx.input.get_char(captured_index) {
                         ctx.subj_pointer += 1;
                     } else {
+                        ctx.hit_end |= ctx.exhausted();
                         ctx.subj_pointer = old;
                         return false;
                     }
@@ -29,6 +43,7 @@ pub(crate) fn exec(ctx: &mut Context) -> bool {
                 let old = ctx.subj_pointer;
                 let start = ctx.input.get_char(ctx.subj_pointer);
                 if start.is_none() || x != start.unwrap() {
+                    ctx.hit_end |= start.is_none();
                     return false;
                 }
                 let mut counter = 1;
@@ -42,6 +57,7 @@ pub(crate) fn exec(ctx: &mut Context) -> bool {
                             counter -= 1;
                         }
                     } else {
+                        ctx.hit_end = true;
                         ctx.subj_pointer = old;
                         return false;
                     }
@@ -49,7 +65,8 @@ pub(crate) fn exec(ctx: &mut Context) -> bool {
                 ctx.subj_pointer += 1;
                 ctx.program_counter += 1;
             }
-            Code::Frontier(s) => {
+            Code::Frontier(_) => {
+                let s = &ctx.tables[ctx.program_counter];
                 let prev = if ctx.subj_pointer == 0 {
                     '\0'
                 } else {
@@ -58,6 +75,7 @@ pub(crate) fn exec(ctx: &mut Context) -> bool {
                         .copied()
                         .unwrap_or('\0')
                 };
+                ctx.hit_end |= ctx.exhausted();
                 let current = ctx
                     .input
                     .get_char(ctx.subj_pointer)
@@ -69,15 +87,87 @@ pub(crate) fn exec(ctx: &mut Context) -> bool {
                     return false;
                 }
             }
+            Code::MaxExpand { min, .. } => {
+                let class = &ctx.tables[ctx.program_counter];
+                let start = ctx.subj_pointer;
+                let mut count = 0;
+                while let Some(c) = ctx.input.get_char(start + count) {
+                    if !class.is_matched(*c) {
+                        break;
+                    }
+                    count += 1;
+                }
+                ctx.hit_end |= start + count == ctx.input.len();
+                let (min, next) = (*min, ctx.program_counter + 1);
+                while count >= min {
+                    ctx.subj_pointer = start + count;
+                    ctx.program_counter = next;
+                    if exec(ctx) {
+                        return true;
+                    }
+                    if count == 0 {
+                        break;
+                    }
+                    count -= 1;
+                }
+                ctx.subj_pointer = start;
+                return false;
+            }
+            Code::MinExpand(_) => {
+                let class = &ctx.tables[ctx.program_counter];
+                let (start, pc) = (ctx.subj_pointer, ctx.program_counter);
+                let mut count = 0;
+                loop {
+                    ctx.subj_pointer = start + count;
+                    ctx.program_counter = pc + 1;
+                    if exec(ctx) {
+                        return true;
+                    }
+                    match ctx.input.get_char(start + count) {
+                        Some(c) if class.is_matched(*c) => count += 1,
+                        Some(_) => break,
+                        None => {
+                            ctx.hit_end = true;
+                            break;
+                        }
+                    }
+                }
+                ctx.subj_pointer = start;
+                return false;
+            }
+            Code::Optional(_) => {
+                let class = &ctx.tables[ctx.program_counter];
+                let (old, next) = (ctx.subj_pointer, ctx.program_counter + 1);
+                ctx.hit_end |= ctx.exhausted();
+                if let Some(c) = ctx.input.get_char(old) {
+                    if class.is_matched(*c) {
+                        ctx.subj_pointer += 1;
+                        ctx.program_counter = next;
+                        if exec(ctx) {
+                            return true;
+                        }
+                        ctx.subj_pointer = old;
+                    }
+                }
+                ctx.program_counter = next;
+            }
             Code::Jmp(x) => ctx.program_counter = *x,
             Code::Split { x, y } => {
+                let old = ctx.subj_pointer;
                 ctx.program_counter = *x;
                 if exec(ctx) {
                     return true;
                 } else {
+                    ctx.subj_pointer = old;
                     ctx.program_counter = *y;
                 }
             }
+            Code::Save(x) if !ctx.track_captures => {
+                if *x < 2 {
+                    ctx.saved[*x] = ctx.subj_pointer;
+                }
+                ctx.program_counter += 1;
+            }
             Code::Save(x) => {
                 let slot = *x;
                 let old = ctx.saved[slot];
@@ -90,7 +180,7 @@ pub(crate) fn exec(ctx: &mut Context) -> bool {
                     return false;
                 }
             }
-            Code::Match => return true,
+            Code::Match => return !ctx.anchor_end || ctx.exhausted(),
         }
     }
 }
@@ -109,12 +199,14 @@ mod test {
         let pcre = RegexBuilder::new()
             .ucp(true)
             .utf(true)
-            .build(r"\w+&?")
+            .build(r"[^\W_]+&?")
             .unwrap();
         let pcre_m = pcre.find_iter(b"bab__&&&ghi").collect::<Vec<_>>();
         assert_eq!(pcre_m.len(), m.len());
         for (m, pcre_m) in m.iter().zip(pcre_m.iter().map(|m| m.as_ref().unwrap())) {
-            let Capture::Value(s1) = m.capture(0).unwrap() else {todo!()};
+            let Capture::Value(s1) = m.capture(0).unwrap() else {
+                todo!()
+            };
             let s2 = &subj[pcre_m.start()..pcre_m.end()];
             assert_eq!(s1, s2);
         }
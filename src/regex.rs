@@ -1,138 +1,902 @@
-use std::ops::Range;
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+};
 
 use crate::{
     bytecode::{
         character_class::CharacterClass,
+        class_table::ClassTable,
         code::Code,
         context::Context,
-        lexer::{lex, PatternElement, Quantifier},
+        encoding::{decode, encode, verify, Encoded},
+        lexer::{try_lex, PatternElement, Quantifier},
+        optimizer::optimize,
     },
-    input::Input,
-    Match,
+    dfa::Dfa,
+    error::{DecodeError, Error, ErrorKind},
+    grep::Grep,
+    overlapping::OverlappingMatches,
+    split::{Matches, Split, SplitCaptures, SplitInclusive, SplitN},
+    Captures, Match,
 };
 
+pub use crate::bytecode::context::Scratch;
+
 pub fn compile(re: &str) -> Regex {
     Regex::new(re)
 }
 
-#[derive(Debug)]
+pub fn compile_with(re: &str, options: CompileOptions) -> Regex {
+    Regex::with_options(re, options)
+}
+
+/// Same as [`compile`] but reports a malformed pattern instead of panicking.
+pub fn try_compile(re: &str) -> Result<Regex, Error> {
+    Regex::try_new(re)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CompileOptions {
+    /// Counted loops for quantified items and the peephole optimizer.
+    /// Turn it off to get the plain `Split`/`Jmp` program, e.g. to compare results.
+    pub optimize: bool,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self { optimize: true }
+    }
+}
+
+/// How much of the pattern an incomplete subject matches, see [`Regex::partial_match`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartialMatch {
+    /// The pattern matches the whole subject.
+    Full,
+    /// No match yet, but one may follow once more chars are appended.
+    Prefix,
+    /// No chars appended to the subject can make it match.
+    Never,
+}
+
+/// Compiled pattern. Clones share the program, and any number of threads may
+/// match with one `Regex` at the same time.
+#[derive(Debug, Clone)]
 pub struct Regex {
-    program: Box<[Code]>,
-    anchor_start: bool,
-    anchor_end: bool,
-    captures: usize,
+    pub(crate) program: Arc<Program>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Program {
+    pub(crate) code: Box<[Code]>,
+    pub(crate) tables: Box<[ClassTable]>,
+    pub(crate) anchor_start: bool,
+    pub(crate) anchor_end: bool,
+    pub(crate) captures: usize,
+    /// Bit `n` is set when capture `n` is a position capture `()`.
+    pub(crate) position_captures: u16,
+    backreferences: bool,
+    literal: Option<Box<str>>,
+    /// Most chars a match can take, `None` when unbounded.
+    width: Option<usize>,
+    dfa: Option<Dfa>,
+    pool: Mutex<Vec<Scratch>>,
 }
 
 impl Regex {
     pub fn new(re: &str) -> Self {
-        let mut prog = vec![Code::Save(0)];
-        let mut captures = 0;
-        let mut saves = 0;
-        let anchor_start = re.starts_with('^');
-        let anchor_end = re.ends_with('$');
-        let re = re.strip_prefix('^').unwrap_or(re);
-        let re = re.strip_suffix('$').unwrap_or(re);
-
-        for (lex, quantifier) in lex(re) {
-            if let PatternElement::SaveOpen(n) = &lex {
-                saves += 1;
-                if *n > captures {
-                    captures = *n;
+        Self::with_options(re, CompileOptions::default())
+    }
+
+    pub fn with_options(re: &str, options: CompileOptions) -> Self {
+        Self::compile(re, options).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    pub fn try_new(re: &str) -> Result<Self, Error> {
+        Self::try_with_options(re, CompileOptions::default())
+    }
+
+    pub fn try_with_options(re: &str, options: CompileOptions) -> Result<Self, Error> {
+        Self::compile(re, options)
+    }
+
+    pub fn match_one<'a>(&self, subj: &'a str) -> Option<Match<'a>> {
+        let mut captures = Captures::new();
+        self.captures_read(&mut captures, subj)
+            .then(|| captures.to_match(subj))
+    }
+
+    pub fn match_all<'a>(&self, subj: &'a str) -> Box<[Match<'a>]> {
+        if let Some(literal) = &self.program.literal {
+            let mut matches = vec![];
+            let mut from = 0;
+            while let Some(found) = self.find_literal(literal, subj, from) {
+                from = found.end;
+                matches.push(Match {
+                    subj,
+                    captures: Box::new([found]),
+                    position_captures: 0,
+                });
+            }
+            return matches.into();
+        }
+        self.with_scratch(|scratch| {
+            let mut matches = vec![];
+            let mut ranges = vec![];
+            let mut ctx = self.context(subj, std::mem::take(scratch));
+            while !ctx.exhausted() {
+                if self.find_match_ranges(&mut ctx, &mut ranges) {
+                    matches.push(Match {
+                        subj,
+                        captures: ranges.as_slice().into(),
+                        position_captures: self.program.position_captures,
+                    });
+                }
+                if self.program.anchor_start {
+                    break;
                 }
             }
-            if let PatternElement::SaveClose(_) = &lex {
-                saves -= 1;
+            *scratch = ctx.into_scratch();
+            matches.into()
+        })
+    }
+
+    /// Attempts a match at every char of `subj` and the end of it, so unlike
+    /// [`Regex::match_all`] the matches may overlap: `%a%a` finds `ab` and `bc` in `abc`.
+    pub fn overlapping_matches<'a>(&'a self, subj: &'a str) -> OverlappingMatches<'a> {
+        OverlappingMatches::new(self, subj, 1)
+    }
+
+    /// Same as [`Regex::overlapping_matches`] at every `stride`-th char only.
+    /// Panics if `stride` is 0.
+    pub fn overlapping_matches_with_stride<'a>(
+        &'a self,
+        subj: &'a str,
+        stride: usize,
+    ) -> OverlappingMatches<'a> {
+        OverlappingMatches::new(self, subj, stride)
+    }
+
+    /// The lines of `text` with matches, see [`Grep`].
+    pub fn grep<'a>(&'a self, text: &'a str) -> Grep<'a, &'a [u8]> {
+        Grep::new(self, text.as_bytes())
+    }
+
+    /// Tells whether the pattern matches anywhere in `subj`.
+    /// Runs in linear time on the DFA when the pattern has no `%1`, `%b` and `%f`,
+    /// otherwise captures are not recorded unless a back-reference needs them.
+    pub fn is_match(&self, subj: &str) -> bool {
+        if self.program.literal.is_some() {
+            return self.find(subj).is_some();
+        }
+        if let Some(dfa) = &self.program.dfa {
+            return dfa.is_match(subj);
+        }
+        self.with_scratch(|scratch| {
+            let mut ctx = self.capture_free_context(subj, std::mem::take(scratch));
+            let found = self.search(&mut ctx);
+            *scratch = ctx.into_scratch();
+            found
+        })
+    }
+
+    /// Finds the byte range of the first match in `subj`, on the DFA when possible.
+    pub fn find(&self, subj: &str) -> Option<Range<usize>> {
+        if let Some(literal) = &self.program.literal {
+            return self.find_literal(literal, subj, 0);
+        }
+        match &self.program.dfa {
+            Some(dfa) => dfa.find(subj),
+            None => self.find_backtracking(subj),
+        }
+    }
+
+    /// Tells whether the pattern matches the whole of `subj`, as if it were
+    /// wrapped in `^` and `$`. The engine backtracks until the match reaches the end.
+    pub fn is_full_match(&self, subj: &str) -> bool {
+        if let Some(literal) = &self.program.literal {
+            return subj == &**literal;
+        }
+        self.with_scratch(|scratch| {
+            let mut ctx = self.capture_free_context(subj, std::mem::take(scratch));
+            ctx.anchor_end = true;
+            let found = self.match_here(&mut ctx, 0);
+            *scratch = ctx.into_scratch();
+            found
+        })
+    }
+
+    /// Same as [`Regex::is_full_match`] with the captures of the match.
+    pub fn full_match<'a>(&self, subj: &'a str) -> Option<Match<'a>> {
+        if let Some(literal) = &self.program.literal {
+            let whole = 0..subj.len();
+            return (subj == &**literal).then(|| Match {
+                subj,
+                captures: Box::new([whole]),
+                position_captures: 0,
+            });
+        }
+        self.with_scratch(|scratch| {
+            let mut ranges = vec![];
+            let mut ctx = self.context(subj, std::mem::take(scratch));
+            ctx.anchor_end = true;
+            if self.match_here(&mut ctx, 0) {
+                ctx.read_captured_ranges(&mut ranges);
             }
-            let code = code_for_lex(lex);
-            let pc = prog.len();
-            match quantifier {
-                Quantifier::ExactlyOne => {
-                    prog.push(code);
+            *scratch = ctx.into_scratch();
+            (!ranges.is_empty()).then(|| Match {
+                subj,
+                captures: ranges.into(),
+                position_captures: self.program.position_captures,
+            })
+        })
+    }
+
+    /// Tells whether `subj` fully matches, as in [`Regex::is_full_match`], or whether
+    /// it could still match with more chars appended, e.g. while a form field is
+    /// being typed. It is a [`PartialMatch::Prefix`] when some thread of the program
+    /// ran out of chars before failing.
+    pub fn partial_match(&self, subj: &str) -> PartialMatch {
+        if let Some(literal) = &self.program.literal {
+            return match literal.strip_prefix(subj) {
+                Some("") => PartialMatch::Full,
+                Some(_) => PartialMatch::Prefix,
+                None => PartialMatch::Never,
+            };
+        }
+        self.with_scratch(|scratch| {
+            let mut ctx = self.capture_free_context(subj, std::mem::take(scratch));
+            ctx.anchor_end = true;
+            ctx.hit_end = false;
+            let partial = if self.match_here(&mut ctx, 0) {
+                PartialMatch::Full
+            } else if ctx.hit_end {
+                PartialMatch::Prefix
+            } else {
+                PartialMatch::Never
+            };
+            *scratch = ctx.into_scratch();
+            partial
+        })
+    }
+
+    /// Matches the pattern starting exactly at byte `offset` of `subj`, like
+    /// `string.find(s, "^pattern", init)`: `^` changes nothing, `$` is the end of
+    /// `subj` and `%f` sees the char before `offset`. Only that char and the
+    /// text from `offset` on are decoded.
+    /// Panics if `offset` is not a char boundary of `subj`.
+    pub fn match_at<'a>(&self, subj: &'a str, offset: usize) -> Option<Match<'a>> {
+        assert!(
+            subj.is_char_boundary(offset),
+            "{} is not a char boundary of the subject.",
+            offset
+        );
+        if let Some(literal) = &self.program.literal {
+            return self.literal_at(literal, subj, offset).map(|found| Match {
+                subj,
+                captures: Box::new([found]),
+                position_captures: 0,
+            });
+        }
+        let from = char_before(subj, offset);
+        self.with_scratch(|scratch| {
+            let mut ranges = vec![];
+            let mut ctx = self.context(&subj[from..], std::mem::take(scratch));
+            if self.match_here(&mut ctx, (from < offset) as usize) {
+                ctx.read_captured_ranges(&mut ranges);
+            }
+            *scratch = ctx.into_scratch();
+            (!ranges.is_empty()).then(|| Match {
+                subj,
+                captures: ranges
+                    .into_iter()
+                    .map(|r| from + r.start..from + r.end)
+                    .collect(),
+                position_captures: self.program.position_captures,
+            })
+        })
+    }
+
+    /// Same as [`Regex::match_at`] without recording captures.
+    pub fn is_match_at(&self, subj: &str, offset: usize) -> bool {
+        assert!(
+            subj.is_char_boundary(offset),
+            "{} is not a char boundary of the subject.",
+            offset
+        );
+        if let Some(literal) = &self.program.literal {
+            return self.literal_at(literal, subj, offset).is_some();
+        }
+        let from = char_before(subj, offset);
+        self.with_scratch(|scratch| {
+            let mut ctx = self.capture_free_context(&subj[from..], std::mem::take(scratch));
+            let found = self.match_here(&mut ctx, (from < offset) as usize);
+            *scratch = ctx.into_scratch();
+            found
+        })
+    }
+
+    /// Finds the byte range of the last match in `subj`, the one starting furthest
+    /// right like `str::rfind` does. While the match starting one char earlier ends
+    /// at the same place or later it is taken instead, so `%d+` finds all of the
+    /// last number. Candidate starts are tried from the end of `subj` backwards.
+    pub fn rfind(&self, subj: &str) -> Option<Range<usize>> {
+        if let Some(literal) = &self.program.literal {
+            return self.rfind_literal(literal, subj);
+        }
+        self.with_scratch(|scratch| {
+            let mut ctx = self.capture_free_context(subj, std::mem::take(scratch));
+            let found = self
+                .search_backwards(&mut ctx)
+                .then(|| ctx.captured_range(0));
+            *scratch = ctx.into_scratch();
+            found
+        })
+    }
+
+    /// Same as [`Regex::rfind`] with the captures of the match.
+    pub fn find_last<'a>(&self, subj: &'a str) -> Option<Match<'a>> {
+        if let Some(literal) = &self.program.literal {
+            return self.rfind_literal(literal, subj).map(|found| Match {
+                subj,
+                captures: Box::new([found]),
+                position_captures: 0,
+            });
+        }
+        self.with_scratch(|scratch| {
+            let mut ranges = vec![];
+            let mut ctx = self.context(subj, std::mem::take(scratch));
+            if self.search_backwards(&mut ctx) {
+                ctx.read_captured_ranges(&mut ranges);
+            }
+            *scratch = ctx.into_scratch();
+            (!ranges.is_empty()).then(|| Match {
+                subj,
+                captures: ranges.into(),
+                position_captures: self.program.position_captures,
+            })
+        })
+    }
+
+    /// Replaces every match like Lua's `string.gsub`: `%0` in `repl` is the whole
+    /// match, `%1`-`%9` are captures and `%%` is a percent sign. Matches are found
+    /// the way [`Regex::split`] finds them, so `x*` turns `"abc"` into `"-a-b-c-"`.
+    ///
+    /// Panics on a malformed `repl` when there is a match, see [`Regex::try_replace`].
+    pub fn replace(&self, subj: &str, repl: &str) -> String {
+        self.replacen(subj, repl, usize::MAX)
+    }
+
+    /// Same as [`Regex::replace`] for at most `n` first matches.
+    pub fn replacen(&self, subj: &str, repl: &str, n: usize) -> String {
+        self.try_replacen(subj, repl, n)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Same as [`Regex::replace`] but reports a malformed `repl` instead of panicking.
+    pub fn try_replace(&self, subj: &str, repl: &str) -> Result<String, Error> {
+        self.try_replacen(subj, repl, usize::MAX)
+    }
+
+    /// Same as [`Regex::replacen`] but reports a malformed `repl` instead of panicking.
+    pub fn try_replacen(&self, subj: &str, repl: &str, n: usize) -> Result<String, Error> {
+        let mut replaced = String::with_capacity(subj.len());
+        let mut last = 0;
+        for m in Matches::new(self, subj).take(n) {
+            let whole = m.captures[0].clone();
+            replaced.push_str(&subj[last..whole.start]);
+            m.try_expand(repl, &mut replaced)?;
+            last = whole.end;
+        }
+        replaced.push_str(&subj[last..]);
+        Ok(replaced)
+    }
+
+    /// Splits `subj` into the pieces between matches. Matches are found the way
+    /// Lua 5.4's `string.gmatch` finds them, so an empty match right after another
+    /// match is skipped and `x*` splits `"ab"` into `"", "a", "b", ""`.
+    pub fn split<'a>(&'a self, subj: &'a str) -> Split<'a> {
+        Split::new(self, subj)
+    }
+
+    /// Same as [`Regex::split`] but yields at most `n` pieces, the last one being
+    /// the rest of `subj`.
+    pub fn splitn<'a>(&'a self, subj: &'a str, n: usize) -> SplitN<'a> {
+        SplitN::new(self, subj, n)
+    }
+
+    /// The pieces of [`Regex::split`] from the last one.
+    pub fn rsplit<'a>(&'a self, subj: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.split(subj).collect::<Vec<_>>().into_iter().rev()
+    }
+
+    /// Same as [`Regex::split`] but every piece ends with the separator after it.
+    /// Empty pieces are left out.
+    pub fn split_inclusive<'a>(&'a self, subj: &'a str) -> SplitInclusive<'a> {
+        SplitInclusive::new(self, subj)
+    }
+
+    /// Same as [`Regex::split`] with every separator's [`Match`] yielded after the
+    /// piece before it.
+    pub fn split_captures<'a>(&'a self, subj: &'a str) -> SplitCaptures<'a> {
+        SplitCaptures::new(self, subj)
+    }
+
+    /// Removes non-empty matches from the start of `subj` for as long as there are
+    /// any, so `%s` trims leading spaces and `0` leading zeros.
+    pub fn trim_start<'a>(&self, subj: &'a str) -> &'a str {
+        self.with_scratch(|scratch| {
+            let mut ctx = self.context(subj, std::mem::take(scratch));
+            let mut start = 0;
+            while self.match_here(&mut ctx, start) && ctx.saved[1] > start {
+                start = ctx.saved[1];
+                if self.program.anchor_start {
+                    break;
                 }
-                Quantifier::ZeroOrOne => {
-                    prog.push(Code::Split {
-                        x: pc + 1,
-                        y: pc + 2,
-                    });
-                    prog.push(code)
+            }
+            let trimmed = &subj[ctx.input.get_byte_index(start).unwrap()..];
+            *scratch = ctx.into_scratch();
+            trimmed
+        })
+    }
+
+    /// Removes non-empty matches from the end of `subj` for as long as there are
+    /// any, each time the longest one ending there. Patterns the lazy DFA runs
+    /// are matched backwards from the end, so only the tail of `subj` is read;
+    /// others are tried from every start their match could begin at.
+    pub fn trim_end<'a>(&self, subj: &'a str) -> &'a str {
+        if let Some(dfa) = &self.program.dfa {
+            let mut end = subj.len();
+            while end > 0 {
+                let start = dfa.find_start(&subj[..end]);
+                if start == end || self.program.anchor_start && start > 0 {
+                    break;
                 }
-                Quantifier::OneOrMany => {
-                    prog.push(code);
-                    prog.push(Code::Split { x: pc, y: pc + 2 });
+                end = start;
+            }
+            return &subj[..end];
+        }
+        self.with_scratch(|scratch| {
+            let mut ctx = self.context(subj, std::mem::take(scratch));
+            ctx.anchor_end = true;
+            while let Some(start) = self.match_before_end(&mut ctx) {
+                ctx.input.truncate(start);
+            }
+            let trimmed = &subj[..ctx.input.subj.len()];
+            *scratch = ctx.into_scratch();
+            trimmed
+        })
+    }
+
+    /// Same as [`Regex::trim_end`] after [`Regex::trim_start`], like
+    /// `s:match("^%s*(.-)%s*$")` for `%s`.
+    pub fn trim<'a>(&self, subj: &'a str) -> &'a str {
+        self.trim_end(self.trim_start(subj))
+    }
+
+    /// The compiled program in a self-contained binary form, see [`Regex::from_bytes`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode(&Encoded {
+            code: self.program.code.to_vec(),
+            anchor_start: self.program.anchor_start,
+            anchor_end: self.program.anchor_end,
+            captures: self.program.captures,
+        })
+    }
+
+    /// Loads a program written by [`Regex::to_bytes`] without compiling the pattern.
+    /// The program is verified first, so bytes from elsewhere cannot make matching
+    /// index out of bounds, loop forever or yield a capture ending before it starts.
+    /// As for compiled patterns, the VM recurses once per `Save` and `Split` a thread
+    /// goes through and once per char a loop takes, so a long enough program or
+    /// subject can still overflow the stack.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let Encoded {
+            code,
+            anchor_start,
+            anchor_end,
+            captures,
+        } = decode(bytes)?;
+        verify(&code, captures)?;
+        let literal = literal_of(&code);
+        Ok(Self::from_code(
+            code,
+            anchor_start,
+            anchor_end,
+            captures,
+            literal,
+        ))
+    }
+
+    /// Finds the first match in `subj` and writes its capture ranges into `captures`,
+    /// reusing the buffers of both `captures` and an internal pool of [`Scratch`]es.
+    /// Returns `false` and leaves `captures` empty when there is no match.
+    pub fn captures_read(&self, captures: &mut Captures, subj: &str) -> bool {
+        self.with_scratch(|scratch| self.captures_read_with(scratch, captures, subj))
+    }
+
+    /// Same as [`Regex::captures_read`] but with a caller-provided [`Scratch`].
+    pub fn captures_read_with(
+        &self,
+        scratch: &mut Scratch,
+        captures: &mut Captures,
+        subj: &str,
+    ) -> bool {
+        captures.position_captures = self.program.position_captures;
+        if let Some(literal) = &self.program.literal {
+            captures.ranges.clear();
+            captures.ranges.extend(self.find_literal(literal, subj, 0));
+            return !captures.is_empty();
+        }
+        let mut ctx = self.context(subj, std::mem::take(scratch));
+        let found = self.find_match_ranges(&mut ctx, &mut captures.ranges);
+        *scratch = ctx.into_scratch();
+        found
+    }
+}
+
+impl Regex {
+    fn compile(pattern: &str, options: CompileOptions) -> Result<Self, Error> {
+        let mut prog = vec![Code::Save(0)];
+        let mut captures = 0;
+        let mut saves = vec![];
+        let anchor_start = pattern.starts_with('^');
+        let re = pattern.strip_prefix('^').unwrap_or(pattern);
+        let offset = pattern.len() - re.len();
+        // `%$` is an escaped `$`, `%%$` a percent sign followed by the anchor.
+        let anchor_end = re
+            .strip_suffix('$')
+            .is_some_and(|rest| rest.bytes().rev().take_while(|&b| b == b'%').count() % 2 == 0);
+        let re = if anchor_end { &re[..re.len() - 1] } else { re };
+
+        for lexed in try_lex(re) {
+            let (position, lex, quantifier) =
+                lexed.map_err(|e| Error::new(e.position + offset, e.kind))?;
+            match &lex {
+                PatternElement::SaveOpen(n) => {
+                    saves.push((*n, position + offset));
+                    if *n > captures {
+                        captures = *n;
+                    }
                 }
-                Quantifier::ZeroOrManyGreedy => {
-                    prog.push(Code::Split {
-                        x: pc + 1,
-                        y: pc + 3,
-                    });
-                    prog.push(code);
-                    prog.push(Code::Jmp(pc));
+                PatternElement::SaveClose(_) => {
+                    saves.pop();
                 }
-                Quantifier::ZeroOrManyUngreedy => {
-                    prog.push(Code::Split {
-                        x: pc + 3,
-                        y: pc + 1,
-                    });
-                    prog.push(code);
-                    prog.push(Code::Jmp(pc));
+                PatternElement::Captured(n)
+                    if saves.iter().any(|(open, _)| open == n) || *n > captures =>
+                {
+                    return Err(Error::new(position + offset, ErrorKind::NoSuchCapture(*n)));
                 }
+                _ => {}
             }
+            let code = code_for_lex(lex);
+            if options.optimize {
+                push_counted(&mut prog, code, quantifier);
+            } else {
+                push_split(&mut prog, code, quantifier);
+            }
+        }
+        if let Some(&(_, position)) = saves.first() {
+            return Err(Error::new(position, ErrorKind::UnclosedCapture));
         }
         prog.push(Code::Save(1));
         prog.push(Code::Match);
+        if options.optimize {
+            prog = optimize(prog);
+        }
 
-        assert_eq!(0, saves);
-        Self {
-            program: prog.into_boxed_slice(),
+        let literal = if options.optimize {
+            literal_of(&prog)
+        } else {
+            None
+        };
+        Ok(Self::from_code(
+            prog,
+            anchor_start,
+            anchor_end,
+            captures,
+            literal,
+        ))
+    }
+
+    fn from_code(
+        code: Vec<Code>,
+        anchor_start: bool,
+        anchor_end: bool,
+        captures: usize,
+        literal: Option<Box<str>>,
+    ) -> Self {
+        let backreferences = code.iter().any(|code| matches!(code, Code::Captured(_)));
+        let tables = code
+            .iter()
+            .map(|code| code.class().map(ClassTable::new).unwrap_or_default())
+            .collect::<Box<[_]>>();
+        let dfa = Dfa::new(&code, &tables, anchor_start, anchor_end);
+        let width = max_width(&code);
+        let position_captures = position_captures(&code);
+        let program = Program {
+            code: code.into_boxed_slice(),
+            tables,
             anchor_start,
             anchor_end,
             captures,
+            position_captures,
+            backreferences,
+            literal,
+            width,
+            dfa,
+            pool: Mutex::new(vec![]),
+        };
+        Self {
+            program: Arc::new(program),
         }
     }
 
-    pub fn match_one<'a>(&self, subj: &'a str) -> Option<Match<'a>> {
-        let mut ctx = Context::new(&self.program, Input::new(subj), self.captures);
-        self.find_match_ranges(&mut ctx)
-            .map(|captures| Match { subj, captures })
+    pub(crate) fn literal(&self) -> Option<&str> {
+        self.program.literal.as_deref()
     }
 
-    pub fn match_all<'a>(&self, subj: &'a str) -> Box<[Match<'a>]> {
-        let mut matches = vec![];
-        let mut ctx = Context::new(&self.program, Input::new(subj), self.captures);
-        while !ctx.exhausted() {
-            if let Some(captures) = self.find_match_ranges(&mut ctx) {
-                matches.push(Match { subj, captures });
-            }
-            if self.anchor_start {
-                break;
-            }
+    pub(crate) fn context<'a>(&'a self, subj: &'a str, scratch: Scratch) -> Context<'a> {
+        let mut ctx = Context::with_scratch(
+            &self.program.code,
+            &self.program.tables,
+            subj,
+            self.program.captures,
+            scratch,
+        );
+        ctx.anchor_end = self.program.anchor_end;
+        ctx
+    }
+
+    fn with_scratch<T>(&self, f: impl FnOnce(&mut Scratch) -> T) -> T {
+        let mut scratch = self.program.pool.lock().unwrap().pop().unwrap_or_default();
+        let result = f(&mut scratch);
+        self.program.pool.lock().unwrap().push(scratch);
+        result
+    }
+
+    /// Plain substring search standing in for the program of a literal pattern.
+    pub(crate) fn find_literal(
+        &self,
+        literal: &str,
+        subj: &str,
+        from: usize,
+    ) -> Option<Range<usize>> {
+        let start = match (self.program.anchor_start, self.program.anchor_end) {
+            (true, true) => (from == 0 && subj == literal).then_some(0)?,
+            (true, false) => (from == 0 && subj.starts_with(literal)).then_some(0)?,
+            (false, true) => subj
+                .strip_suffix(literal)
+                .map(str::len)
+                .filter(|&start| start >= from)?,
+            (false, false) => from + subj[from..].find(literal)?,
+        };
+        Some(start..start + literal.len())
+    }
+
+    fn literal_at(&self, literal: &str, subj: &str, offset: usize) -> Option<Range<usize>> {
+        let end = offset + literal.len();
+        let found =
+            subj[offset..].starts_with(literal) && (!self.program.anchor_end || end == subj.len());
+        found.then_some(offset..end)
+    }
+
+    fn rfind_literal(&self, literal: &str, subj: &str) -> Option<Range<usize>> {
+        if self.program.anchor_start || self.program.anchor_end {
+            return self.find_literal(literal, subj, 0);
         }
-        matches.into()
+        let start = subj.rfind(literal)?;
+        Some(start..start + literal.len())
     }
-}
 
-impl Regex {
-    fn find_match_ranges(&self, ctx: &mut Context) -> Option<Box<[Range<usize>]>> {
+    fn find_backtracking(&self, subj: &str) -> Option<Range<usize>> {
+        self.with_scratch(|scratch| {
+            let mut ctx = self.capture_free_context(subj, std::mem::take(scratch));
+            let found = self.search(&mut ctx).then(|| ctx.captured_range(0));
+            *scratch = ctx.into_scratch();
+            found
+        })
+    }
+
+    fn capture_free_context<'a>(&'a self, subj: &'a str, scratch: Scratch) -> Context<'a> {
+        let mut ctx = self.context(subj, scratch);
+        ctx.track_captures = self.program.backreferences;
+        ctx
+    }
+
+    fn find_match_ranges(&self, ctx: &mut Context, ranges: &mut Vec<Range<usize>>) -> bool {
+        ranges.clear();
+        let found = self.search(ctx);
+        if found {
+            ctx.read_captured_ranges(ranges);
+        }
+        found
+    }
+
+    /// Points `ctx` at this program, keeping the subject it has already decoded.
+    pub(crate) fn attach<'a>(&'a self, ctx: &mut Context<'a>) {
+        ctx.program = &self.program.code;
+        ctx.tables = &self.program.tables;
+        ctx.saved.clear();
+        ctx.saved.resize(2 * self.program.captures + 2, 0);
+        ctx.track_captures = true;
+        ctx.anchor_end = self.program.anchor_end;
+    }
+
+    /// Runs the program once for a match starting exactly at char `start`,
+    /// the way `string.find(s, "^pattern", init)` does: `%f` still sees the
+    /// char before `start` and `$` is the end of the whole subject.
+    pub(crate) fn match_here(&self, ctx: &mut Context, start: usize) -> bool {
+        ctx.subj_pointer = start;
         ctx.program_counter = 0;
-        while !ctx.exhausted() {
+        crate::recursive::exec(ctx)
+    }
+
+    /// Smallest start of a non-empty match ending at the end of the subject,
+    /// which is where [`Regex::trim_end`] has cut it so far.
+    fn match_before_end(&self, ctx: &mut Context) -> Option<usize> {
+        let end = ctx.input.len();
+        if self.program.anchor_start {
+            return (end > 0 && self.match_here(ctx, 0)).then_some(0);
+        }
+        let lowest = self
+            .program
+            .width
+            .map_or(0, |width| end.saturating_sub(width));
+        (lowest..end).find(|&start| self.match_here(ctx, start))
+    }
+
+    /// Runs the program from the end of the subject backwards for the match
+    /// [`Regex::rfind`] describes and leaves it in the save slots.
+    fn search_backwards(&self, ctx: &mut Context) -> bool {
+        let last = if self.program.anchor_start {
+            0
+        } else {
+            ctx.input.len()
+        };
+        let Some(mut start) = (0..=last).rev().find(|&start| self.match_here(ctx, start)) else {
+            return false;
+        };
+        let end = ctx.saved[1];
+        while start > 0 && self.match_here(ctx, start - 1) && ctx.saved[1] >= end {
+            start -= 1;
+        }
+        self.match_here(ctx, start)
+    }
+
+    /// Runs the program from every position starting at the subject pointer
+    /// until the first match. On success the save slots hold the match and the
+    /// subject pointer is left where the next search should start.
+    pub(crate) fn search(&self, ctx: &mut Context) -> bool {
+        while ctx.subj_pointer <= ctx.input.len() {
+            let start = ctx.subj_pointer;
+            ctx.program_counter = 0;
             if crate::recursive::exec(ctx) {
-                if !self.anchor_end || ctx.exhausted() {
-                    let matches = ctx.captured_ranges();
-                    println!("Found: {:?}", ctx.saved);
-                    if matches.first().unwrap().is_empty() {
-                        ctx.subj_pointer += 1;
-                    }
-                    return Some(matches);
+                if ctx.subj_pointer == start {
+                    ctx.subj_pointer += 1;
                 }
-            } else {
-                ctx.subj_pointer += 1;
+                return true;
             }
-            ctx.program_counter = 0;
-            if self.anchor_start {
+            ctx.subj_pointer = start + 1;
+            if self.program.anchor_start {
                 break;
             }
         }
-        None
+        false
+    }
+}
+
+/// Byte where the char before `offset` starts, `offset` itself at the start of `subj`.
+fn char_before(subj: &str, offset: usize) -> usize {
+    subj[..offset]
+        .char_indices()
+        .next_back()
+        .map_or(offset, |(i, _)| i)
+}
+
+/// The non-empty string a program matches when it is nothing but literals.
+fn literal_of(prog: &[Code]) -> Option<Box<str>> {
+    let [Code::Save(0), body @ .., Code::Save(1), Code::Match] = prog else {
+        return None;
+    };
+    let mut literal = String::new();
+    for code in body {
+        match code {
+            Code::Char(CharacterClass::Literal(c)) => literal.push(*c),
+            Code::Chars(s) => literal.extend(s.iter()),
+            _ => return None,
+        }
+    }
+    (!literal.is_empty()).then(|| literal.into())
+}
+
+/// The captures opened and closed by adjacent saves, which only `()` compiles to.
+/// The whole match is never a position, even when the pattern is empty.
+fn position_captures(prog: &[Code]) -> u16 {
+    prog.windows(2)
+        .filter_map(|pair| match *pair {
+            [Code::Save(open), Code::Save(close)]
+                if open >= 2 && open % 2 == 0 && close == open + 1 =>
+            {
+                Some(1 << (open / 2))
+            }
+            _ => None,
+        })
+        .fold(0, |mask, bit| mask | bit)
+}
+
+/// Most chars a match of the program can take, `None` when a loop or an item
+/// without a fixed length makes it unbounded. Only forward jumps are followed,
+/// so one backward pass finds the width from every pc.
+fn max_width(prog: &[Code]) -> Option<usize> {
+    let mut widths: Vec<Option<usize>> = vec![None; prog.len() + 1];
+    for pc in (0..prog.len()).rev() {
+        let next = |target: usize| if target > pc { widths[target] } else { None };
+        let width = match &prog[pc] {
+            Code::Char(_) | Code::Optional(_) => next(pc + 1).map(|w| w + 1),
+            Code::Chars(s) => next(pc + 1).map(|w| w + s.len()),
+            Code::Frontier(_) | Code::Save(_) => next(pc + 1),
+            Code::Jmp(target) => next(*target),
+            Code::Split { x, y } => next(*x).zip(next(*y)).map(|(x, y)| x.max(y)),
+            Code::Captured(_) | Code::Border(..) | Code::MaxExpand { .. } | Code::MinExpand(_) => {
+                None
+            }
+            Code::Match => Some(0),
+        };
+        widths[pc] = width;
+    }
+    widths[0]
+}
+
+fn push_counted(prog: &mut Vec<Code>, code: Code, quantifier: Quantifier) {
+    let class = match (&quantifier, code) {
+        (Quantifier::ExactlyOne, code) => return prog.push(code),
+        (_, Code::Char(class)) => class,
+        _ => unreachable!("Only single char items take quantifiers."),
+    };
+    prog.push(match quantifier {
+        Quantifier::ZeroOrOne => Code::Optional(class),
+        Quantifier::OneOrMany => Code::MaxExpand { class, min: 1 },
+        Quantifier::ZeroOrManyGreedy => Code::MaxExpand { class, min: 0 },
+        Quantifier::ZeroOrManyUngreedy => Code::MinExpand(class),
+        Quantifier::ExactlyOne => unreachable!(),
+    })
+}
+
+fn push_split(prog: &mut Vec<Code>, code: Code, quantifier: Quantifier) {
+    let pc = prog.len();
+    match quantifier {
+        Quantifier::ExactlyOne => {
+            prog.push(code);
+        }
+        Quantifier::ZeroOrOne => {
+            prog.push(Code::Split {
+                x: pc + 1,
+                y: pc + 2,
+            });
+            prog.push(code)
+        }
+        Quantifier::OneOrMany => {
+            prog.push(code);
+            prog.push(Code::Split { x: pc, y: pc + 2 });
+        }
+        Quantifier::ZeroOrManyGreedy => {
+            prog.push(Code::Split {
+                x: pc + 1,
+                y: pc + 3,
+            });
+            prog.push(code);
+            prog.push(Code::Jmp(pc));
+        }
+        Quantifier::ZeroOrManyUngreedy => {
+            prog.push(Code::Split {
+                x: pc + 3,
+                y: pc + 1,
+            });
+            prog.push(code);
+            prog.push(Code::Jmp(pc));
+        }
     }
 }
 
@@ -151,10 +915,18 @@ fn code_for_lex(lex: PatternElement) -> Code {
 
 #[cfg(test)]
 mod test {
-    use super::{compile, CharacterClass::*, Code::*};
+    use super::{
+        compile, compile_with, encode, try_compile, CharacterClass::*, Code::*, CompileOptions,
+        Encoded, PartialMatch, Regex, Scratch,
+    };
+    use crate::{
+        error::{DecodeError, Error, ErrorKind},
+        Captures, LineColumn,
+    };
 
     #[test]
     fn it_works() {
+        let unoptimized = CompileOptions { optimize: false };
         assert_eq!(
             [
                 Save(0),
@@ -165,7 +937,11 @@ mod test {
                 Match
             ]
             .as_slice(),
-            compile("abc").program.as_ref()
+            compile_with("abc", unoptimized).program.code.as_ref()
+        );
+        assert_eq!(
+            [Save(0), Chars(['a', 'b', 'c'].into()), Save(1), Match].as_slice(),
+            compile("abc").program.code.as_ref()
         )
     }
 
@@ -174,16 +950,16 @@ mod test {
         assert_eq!(
             [
                 Save(0),
-                Char(Digit(true)),
-                Split { x: 1, y: 3 },
-                Split { x: 4, y: 6 },
-                Char(Letter(true)),
-                Jmp(3),
-                Split { x: 9, y: 7 },
-                Char(Hexadecimal(false)),
-                Jmp(6),
-                Split { x: 10, y: 11 },
-                Char(Unset(Box::new([
+                MaxExpand {
+                    class: Digit(true),
+                    min: 1
+                },
+                MaxExpand {
+                    class: Letter(true),
+                    min: 0
+                },
+                MinExpand(Hexadecimal(false)),
+                Optional(Unset(Box::new([
                     AlphaNumeric(true),
                     Literal('_'),
                     Literal('.')
@@ -192,7 +968,484 @@ mod test {
                 Match
             ]
             .as_slice(),
-            compile("%d+%a*%X-[^%w_%.]?").program.as_ref()
+            compile("%d+%a*%X-[^%w_%.]?").program.code.as_ref()
         )
     }
+
+    #[test]
+    fn captures_read_reuses_buffers() {
+        let re = compile("(%a+)=(%d+)");
+        let mut scratch = Scratch::new();
+        let mut captures = Captures::new();
+        assert!(re.captures_read_with(&mut scratch, &mut captures, "x: key=42"));
+        assert_eq!([3..9, 3..6, 7..9].as_slice(), captures.as_slice());
+        assert!(re.captures_read(&mut captures, "мир=7"));
+        assert_eq!([0..8, 0..6, 7..8].as_slice(), captures.as_slice());
+        assert!(!re.captures_read_with(&mut scratch, &mut captures, "nothing"));
+        assert!(captures.is_empty());
+    }
+
+    #[test]
+    fn backtracks_subject_pointer() {
+        let m = compile("a*ab").match_one("xaab").unwrap();
+        assert_eq!(Some(&(1..4)), m.captures.first());
+        let m = compile("aab").match_one("aaab").unwrap();
+        assert_eq!(Some(&(1..4)), m.captures.first());
+    }
+
+    #[test]
+    fn is_match_and_find() {
+        let re = compile("(%d+)-(%d+)");
+        assert!(re.is_match("from 10-20"));
+        assert_eq!(Some(5..10), re.find("from 10-20"));
+        assert!(!re.is_match("from 10 to 20"));
+        assert_eq!(None, re.find("from 10 to 20"));
+
+        let re = compile("(%a)%1");
+        assert!(re.program.backreferences);
+        assert_eq!(Some(3..5), re.find("abcdde"));
+        assert!(!re.is_match("abcde"));
+    }
+
+    #[test]
+    fn dfa_agrees_with_backtracking() {
+        let patterns = [
+            "%d%d%d%d%-%d%d%-%d%d",
+            "[%w_]+",
+            "a-b",
+            "^a*ab",
+            "x?y+$",
+            "(%a+)=(%d*)",
+            "%s*$",
+            "a-$",
+            "мы.-а",
+        ];
+        let subjects = [
+            "",
+            "date: 2024-01-29!",
+            "snake_case and CamelCase",
+            "aaab cab",
+            "xyy y xxyyy",
+            "key= val=42",
+            "trailing   ",
+            "Мама мыла раму, мы ели",
+        ];
+        for pattern in patterns {
+            let re = compile(pattern);
+            let dfa = re.program.dfa.as_ref().expect(pattern);
+            for subj in subjects {
+                let expected = re.find_backtracking(subj);
+                assert_eq!(expected, dfa.find(subj), "{:?} in {:?}", pattern, subj);
+                assert_eq!(
+                    expected.is_some(),
+                    dfa.is_match(subj),
+                    "{:?} in {:?}",
+                    pattern,
+                    subj
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn no_dfa_for_captures_dependent_codes() {
+        for pattern in ["(%a)%1", "%b()", "%f[%w]%w+"] {
+            assert!(compile(pattern).program.dfa.is_none());
+        }
+    }
+
+    #[test]
+    fn optimized_agrees_with_unoptimized() {
+        let patterns = [
+            "%d+%a*%X-[^%w_%.]?",
+            "(a*)(a-)b+",
+            "%s*(.-)%s*$",
+            "(%a?)%1",
+            "key=(%w+)",
+            "[a][b-b][%a]c?[^]",
+            "^ab[c]d$",
+            "%f[%a]мы[л]а",
+        ];
+        let subjects = [
+            "",
+            "12ab",
+            "aaab aab",
+            "  trim me  ",
+            "xxyy",
+            "a1_.b key=val",
+            "abcd",
+            "abdc abzd",
+            "Мама мыла раму",
+        ];
+        let unoptimized = CompileOptions { optimize: false };
+        for pattern in patterns {
+            let optimized = compile(pattern);
+            let reference = compile_with(pattern, unoptimized);
+            for subj in subjects {
+                assert_eq!(
+                    reference
+                        .match_all(subj)
+                        .iter()
+                        .map(|m| &m.captures)
+                        .collect::<Vec<_>>(),
+                    optimized
+                        .match_all(subj)
+                        .iter()
+                        .map(|m| &m.captures)
+                        .collect::<Vec<_>>(),
+                    "{:?} in {:?}",
+                    pattern,
+                    subj
+                );
+                assert_eq!(reference.find(subj), optimized.find(subj));
+            }
+        }
+    }
+
+    #[test]
+    fn literal_fast_path() {
+        let unoptimized = CompileOptions { optimize: false };
+        let subjects = ["", "a.b", "xa.ba.b", "a.bc", "ca.b", "мир a.b мир"];
+        for pattern in ["a%.b", "^a%.b", "a%.b$", "^a%.b$", "мир"] {
+            let literal = compile(pattern);
+            let reference = compile_with(pattern, unoptimized);
+            assert!(literal.program.literal.is_some());
+            for subj in subjects {
+                assert_eq!(
+                    reference.match_one(subj).map(|m| m.captures),
+                    literal.match_one(subj).map(|m| m.captures),
+                );
+                assert_eq!(
+                    reference
+                        .match_all(subj)
+                        .iter()
+                        .map(|m| &m.captures)
+                        .collect::<Vec<_>>(),
+                    literal
+                        .match_all(subj)
+                        .iter()
+                        .map(|m| &m.captures)
+                        .collect::<Vec<_>>(),
+                );
+                assert_eq!(reference.find(subj), literal.find(subj));
+                assert_eq!(
+                    reference.replace(subj, "<%0>"),
+                    literal.replace(subj, "<%0>")
+                );
+            }
+        }
+        assert!(compile("a.b").program.literal.is_none());
+    }
+
+    #[test]
+    fn replace_like_gsub() {
+        let re = compile("(%w+)=(%w+)");
+        assert_eq!("b=a, d=c", re.replace("a=b, c=d", "%2=%1"));
+        assert_eq!("b=a, c=d", re.replacen("a=b, c=d", "%2=%1", 1));
+        assert_eq!(
+            "[hello] [world]",
+            compile("%a+").replace("hello world", "[%1]")
+        );
+        assert_eq!("100%", compile("%d+").replace("42", "100%%"));
+        assert_eq!("-a-b-c-", compile("x*").replace("abc", "-"));
+        assert_eq!("<a>c<>b<>x<>", compile("a?").replace("acbx", "<%0>"));
+        assert_eq!("<a>cbx", compile("a?").replacen("acbx", "<%0>", 1));
+        assert_eq!("[]", compile("x(a*)y").replace("xy", "[%1]"));
+        assert_eq!("x3", compile("a()b").replace("xab", "%1"));
+        assert_eq!("[]1", compile("x(%f[%d])").replace("x1", "[%1]"));
+
+        assert_eq!(
+            Err(Error::new(3, ErrorKind::NoSuchCapture(3))),
+            re.try_replace("a=b", "%1=%3")
+        );
+        assert_eq!(
+            Err(Error::new(2, ErrorKind::BadReplacement)),
+            re.try_replacen("a=b", "%1%", 1)
+        );
+        assert_eq!(Ok("x".to_owned()), re.try_replace("x", "%"));
+        let mut dst = "kept".to_owned();
+        assert!(re
+            .match_one("a=b")
+            .unwrap()
+            .try_expand("%2%x", &mut dst)
+            .is_err());
+        assert_eq!("kept", dst);
+        assert!(std::panic::catch_unwind(|| re.replace("a=b", "%")).is_err());
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Regex>();
+
+        let re = compile("(%a+)=(%d+)");
+        let copy = re.clone();
+        assert!(std::sync::Arc::ptr_eq(&re.program, &copy.program));
+        std::thread::scope(|scope| {
+            for n in 0..8 {
+                let re = if n % 2 == 0 { &re } else { &copy };
+                scope.spawn(move || {
+                    for i in 0..200 {
+                        let subj = format!("{} key={}", n, i);
+                        let end = subj.len();
+                        let m = re.match_one(&subj).unwrap();
+                        assert_eq!([2..end, 2..5, 6..end].as_slice(), m.captures.as_ref());
+                        assert!(re.is_match(&subj));
+                        assert_eq!(Some(2..end), re.find(&subj));
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn malformed_patterns() {
+        let cases = [
+            ("^(%a+)%2", 6, ErrorKind::NoSuchCapture(2)),
+            ("(a%1)", 2, ErrorKind::NoSuchCapture(1)),
+            ("^a(b(c)$", 2, ErrorKind::UnclosedCapture),
+            ("^[a-", 1, ErrorKind::MissingBracket),
+        ];
+        for (re, position, kind) in cases {
+            assert_eq!(
+                Some(Error::new(position, kind)),
+                try_compile(re).err(),
+                "{}",
+                re
+            );
+        }
+        assert!(Regex::try_new("(%d+)-%1").unwrap().is_match("12-12"));
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let unoptimized = CompileOptions { optimize: false };
+        for re in [
+            "^(%a+)=(%d+)$",
+            "(.-)%1",
+            "%f[%w]%w+",
+            "%b()",
+            "hello",
+            "[^,]*,?",
+        ] {
+            for original in [compile(re), compile_with(re, unoptimized)] {
+                let loaded = Regex::from_bytes(&original.to_bytes()).unwrap();
+                assert_eq!(original.program.code, loaded.program.code);
+                for subj in ["key=42", "abab", "  (a(b)) words", "say hello", "a,b,c"] {
+                    assert_eq!(
+                        original.match_one(subj).map(|m| m.captures),
+                        loaded.match_one(subj).map(|m| m.captures),
+                        "{} on {}",
+                        re,
+                        subj
+                    );
+                    assert_eq!(original.find(subj), loaded.find(subj));
+                }
+            }
+        }
+        for (re, options) in [
+            ("%a".repeat(2000), CompileOptions::default()),
+            ("a?".repeat(600), unoptimized),
+        ] {
+            let original = compile_with(&re, options);
+            let loaded = Regex::from_bytes(&original.to_bytes()).unwrap();
+            assert_eq!(original.program.code, loaded.program.code);
+            assert_eq!(
+                original.match_one("aaa b").map(|m| m.captures),
+                loaded.match_one("aaa b").map(|m| m.captures)
+            );
+        }
+        let mut bytes = compile("(a)%1").to_bytes();
+        assert_eq!(Some(&11), bytes.last());
+        bytes.pop();
+        assert_eq!(
+            Some(DecodeError::Truncated),
+            Regex::from_bytes(&bytes).err()
+        );
+        assert_eq!(
+            Some(DecodeError::BadHeader),
+            Regex::from_bytes(b"%d+ and more").err()
+        );
+    }
+
+    #[test]
+    fn loaded_captures_never_end_before_they_start() {
+        let load = |code: Vec<_>, captures| {
+            Regex::from_bytes(&encode(&Encoded {
+                code,
+                anchor_start: false,
+                anchor_end: false,
+                captures,
+            }))
+        };
+        for (code, captures) in [
+            (vec![Char(Any), Char(Any), Save(1), Match], 0),
+            (vec![Save(0), Char(Any), Save(1), Save(0), Match], 0),
+            (
+                vec![Save(0), Save(3), Char(Any), Save(2), Save(1), Match],
+                1,
+            ),
+        ] {
+            assert!(load(code.clone(), captures).is_err(), "{:?}", code);
+        }
+        let re = load(vec![Save(0), Char(Any), Char(Any), Save(1), Match], 0).unwrap();
+        assert_eq!("-c", re.replace("abc", "-"));
+    }
+
+    #[test]
+    fn trims_repeated_matches() {
+        let space = compile("[%s·]");
+        assert_eq!("ж x", space.trim("·· ж x\t·"));
+        assert_eq!("ж x\t·", space.trim_start("·· ж x\t·"));
+        assert_eq!("", space.trim(" · "));
+        assert_eq!("1", compile("0").trim_end("1000"));
+        assert_eq!("x", compile("%s*;").trim_end("x  ;;"));
+        assert_eq!("2a1", compile("^%d").trim("12a1"));
+        assert_eq!("abc", compile("x*").trim("abc"));
+        assert_eq!("a", compile("%s%f[%w]%d+").trim_end("a 1 2"));
+        assert_eq!("x", compile("a.-b").trim_end("xaZbab"));
+        assert_eq!("x(", compile("%b()").trim_end("x((a)()(())"));
+        let long = "a".repeat(1 << 16);
+        assert_eq!(long, compile(".-x").trim_end(&long));
+        assert_eq!(long, compile("%s+").trim_end(&long));
+        assert_eq!(Some(3), compile("a%d?b").program.width);
+        assert_eq!(None, compile("a%d*").program.width);
+        assert_eq!(Some(40000), compile(&"%a".repeat(40000)).program.width);
+    }
+
+    #[test]
+    fn finds_last_match() {
+        let number = compile("%d+");
+        assert_eq!(Some(5..7), number.rfind("v1.2.10"));
+        assert_eq!(Some(4..5), compile(".").rfind("жabc"));
+        assert_eq!(Some(1..3), compile("aa").rfind("aaa"));
+        assert_eq!(Some(2..4), compile("a%a").rfind("abab"));
+        assert_eq!(Some(3..3), compile("%d*").rfind("v1x"));
+        assert_eq!(Some(0..1), compile("^%d").rfind("12"));
+        assert_eq!(Some(1..2), compile("%d$").rfind("12"));
+        assert_eq!(None, number.rfind("v"));
+        let version = compile("(%d+)%.(%d+)").find_last("1.2 and 3.45").unwrap();
+        assert_eq!(vec!["3.45", "3", "45"], version.captured_str().to_vec());
+        assert!(compile("x").find_last("abc").is_none());
+    }
+
+    #[test]
+    fn matches_at_offset() {
+        let word = compile("%f[%w](%w+)");
+        let subj = "ab жы,cd";
+        assert_eq!(
+            vec!["жы", "жы"],
+            word.match_at(subj, 3).unwrap().captured_str().to_vec()
+        );
+        assert!(word.match_at(subj, 7).is_none());
+        assert!(!word.is_match_at(subj, 1));
+        assert!(word.is_match_at(subj, 8));
+        assert!(compile("x*").is_match_at(subj, 2));
+        assert!(compile("^cd$").is_match_at(subj, 8));
+        assert!(!compile("^ab$").is_match_at(subj, 0));
+        assert_eq!(
+            Some(8..10),
+            compile("cd")
+                .match_at(subj, 8)
+                .map(|m| m.captures[0].clone())
+        );
+        assert!(!compile("ab").is_match_at(subj, 8));
+        assert!(compile("%d*").is_match_at(subj, subj.len()));
+        assert_eq!(
+            vec![3..10, 10..10],
+            compile("%f[%S]%S+()")
+                .match_at(subj, 3)
+                .unwrap()
+                .captures
+                .to_vec()
+        );
+        assert!(!compile("%f[%S]").is_match_at(subj, 5));
+    }
+
+    #[test]
+    #[should_panic]
+    fn match_at_inside_char() {
+        compile("a").is_match_at("ж", 1);
+    }
+
+    #[test]
+    fn full_match_spans_the_subject() {
+        let number = compile("(%d+)%.?(%d*)");
+        assert!(number.is_full_match("12.5"));
+        assert!(!number.is_full_match("12.5x"));
+        assert!(!number.is_full_match("x12"));
+        let m = number.full_match("3.25").unwrap();
+        assert_eq!(vec!["3.25", "3", "25"], m.captured_str().to_vec());
+        assert!(compile("a-").is_full_match("aaa"));
+        assert!(compile("%a+%d").is_full_match("ab1"));
+        assert!(compile("^ab$").is_full_match("ab"));
+        assert!(!compile("ab").is_full_match("abab"));
+        assert!(compile("").is_full_match(""));
+
+        // `%$` is a literal dollar sign, not an anchor.
+        assert!(compile("5%$").is_full_match("5$"));
+        assert_eq!(Some(0..2), compile("5%$").find("5$ off"));
+        assert_eq!(Some(2..4), compile("5%%$").find("5%5%"));
+        assert!(compile("5%%$").find("5% off").is_none());
+        assert!(compile("^$").is_full_match(""));
+    }
+
+    #[test]
+    fn partial_match_of_incomplete_input() {
+        use PartialMatch::*;
+        let phone = compile("%d%d%d%-%d%d%d%d");
+        assert_eq!(Prefix, phone.partial_match(""));
+        assert_eq!(Prefix, phone.partial_match("555-"));
+        assert_eq!(Full, phone.partial_match("555-1234"));
+        assert_eq!(Never, phone.partial_match("55a"));
+        assert_eq!(Never, phone.partial_match("555-12345"));
+
+        let email = compile("^[%w.]+@%w+%.com$");
+        assert_eq!(Prefix, email.partial_match("bob.k@mail.co"));
+        assert_eq!(Full, email.partial_match("bob.k@mail.com"));
+        assert_eq!(Never, email.partial_match("bob.k@@"));
+
+        assert_eq!(Full, compile("%d+").partial_match("12"));
+        assert_eq!(Prefix, compile("%b()").partial_match("(a(b)"));
+        assert_eq!(Prefix, compile("a-b").partial_match("aa"));
+        assert_eq!(Prefix, compile("(%a)=%1").partial_match("x="));
+        assert_eq!(Never, compile("(%a)=%1").partial_match("x=y"));
+        assert_eq!(Prefix, compile("ab").partial_match("a"));
+        assert_eq!(Never, compile("ab").partial_match("b"));
+        assert_eq!(Never, compile("").partial_match("x"));
+    }
+
+    #[test]
+    fn match_positions_in_chars_and_lines() {
+        let subj = "x = 1\nжж = \"😀\"()\n";
+        let m = compile("(%S+) = \"(.-)\"()").match_one(subj).unwrap();
+        assert_eq!(vec![6..19, 6..10, 14..18, 19..19], m.captures.to_vec());
+        assert_eq!(vec![6..14, 6..8, 12..13, 14..14], m.char_ranges().to_vec());
+        assert_eq!(vec![6..15, 6..8, 12..14, 15..15], m.utf16_ranges().to_vec());
+        let at = |line, column, utf16_column| LineColumn {
+            line,
+            column,
+            utf16_column,
+        };
+        let lines = m.line_columns();
+        assert_eq!(at(1, 0, 0)..at(1, 8, 9), lines[0]);
+        assert_eq!(at(1, 6, 6)..at(1, 7, 8), lines[2]);
+        assert_eq!(at(1, 8, 9)..at(1, 8, 9), lines[3]);
+        let end = compile("$").match_one(subj).unwrap();
+        assert_eq!(at(2, 0, 0)..at(2, 0, 0), end.line_columns()[0]);
+    }
+
+    #[test]
+    fn impossible_class_compiles() {
+        for re in ["[]", "a[]", "a[]b"] {
+            assert!(Regex::try_new(re).is_ok(), "{}", re);
+        }
+        let re = compile("a[]");
+        assert_eq!(Some(&Match), re.program.code.last());
+        assert!(!re.is_match("a"));
+        assert!(re.find("aa[]").is_none());
+        assert!(re.match_all("aaa").is_empty());
+        let loaded = Regex::from_bytes(&re.to_bytes()).unwrap();
+        assert_eq!(re.program.code, loaded.program.code);
+    }
 }
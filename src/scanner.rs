@@ -0,0 +1,158 @@
+use crate::{
+    bytecode::context::{Context, Scratch},
+    regex::Regex,
+    Match,
+};
+
+/// Cursor over a subject for parsing it piece by piece, like Ruby's `StringScanner`.
+/// Positions are byte offsets. The subject is decoded once, and the decoded
+/// chars are lent to the [`Context`] of every pattern run.
+#[derive(Debug)]
+pub struct Scanner<'a> {
+    subj: &'a str,
+    /// Holds `subj` decoded between runs.
+    scratch: Scratch,
+    position: usize,
+}
+
+impl<'a> Scanner<'a> {
+    pub fn new(subj: &'a str) -> Self {
+        Self {
+            subj,
+            scratch: Context::with_scratch(&[], &[], subj, 0, Scratch::default()).into_scratch(),
+            position: 0,
+        }
+    }
+
+    /// Matches `re` anchored at the cursor and moves the cursor past the match.
+    pub fn scan(&mut self, re: &Regex) -> Option<Match<'a>> {
+        let m = self.check(re)?;
+        self.position = m.captures[0].end;
+        Some(m)
+    }
+
+    /// Same as [`Scanner::scan`] but returns only the length of the match in bytes.
+    pub fn skip(&mut self, re: &Regex) -> Option<usize> {
+        self.scan(re).map(|m| m.captures[0].len())
+    }
+
+    /// Matches `re` anchored at the cursor without moving it.
+    pub fn check(&mut self, re: &Regex) -> Option<Match<'a>> {
+        self.run(re, |ctx, start| re.match_here(ctx, start))
+    }
+
+    /// Searches for `re` from the cursor on and moves the cursor past the match.
+    /// The text skipped over is `&subj[old_pos..m.captures[0].start]`.
+    pub fn scan_until(&mut self, re: &Regex) -> Option<Match<'a>> {
+        let m = self.run(re, |ctx, start| {
+            ctx.subj_pointer = start;
+            re.search(ctx)
+        })?;
+        self.position = m.captures[0].end;
+        Some(m)
+    }
+
+    pub fn pos(&self) -> usize {
+        self.position
+    }
+
+    /// Moves the cursor to byte `pos`, which must be a char boundary of the subject.
+    pub fn set_pos(&mut self, pos: usize) {
+        assert!(
+            self.subj.is_char_boundary(pos),
+            "{} is not a char boundary of the subject.",
+            pos
+        );
+        self.position = pos;
+    }
+
+    /// The part of the subject after the cursor.
+    pub fn rest(&self) -> &'a str {
+        &self.subj[self.position..]
+    }
+
+    pub fn is_end(&self) -> bool {
+        self.position == self.subj.len()
+    }
+
+    /// Runs `f` on a context for `re` with the cursor as a char index.
+    fn run(
+        &mut self,
+        re: &Regex,
+        f: impl FnOnce(&mut Context, usize) -> bool,
+    ) -> Option<Match<'a>> {
+        let scratch = std::mem::take(&mut self.scratch);
+        let mut ctx = Context::with_decoded(&[], &[], self.subj, 0, scratch);
+        re.attach(&mut ctx);
+        let start = ctx.input.get_char_index(self.position).unwrap();
+        let m = f(&mut ctx, start).then(|| {
+            let mut ranges = vec![];
+            ctx.read_captured_ranges(&mut ranges);
+            Match {
+                subj: self.subj,
+                captures: ranges.into(),
+                position_captures: re.program.position_captures,
+            }
+        });
+        self.scratch = ctx.into_scratch();
+        m
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Scanner;
+    use crate::regex::compile;
+
+    #[test]
+    fn parses_header_piece_by_piece() {
+        let word = compile("(%w+)");
+        let colon = compile(":%s*");
+        let line_end = compile("\r?\n");
+        let frontier = compile("%f[%w]%w+");
+        let missing = compile("x");
+        let end = compile("$");
+        let mut scanner = Scanner::new("Host: пример.рф\r\nAccept: */*\r\n");
+
+        let name = scanner.scan(&word).unwrap();
+        assert_eq!(0..4, name.captures[1]);
+        assert_eq!(Some(2), scanner.skip(&colon));
+        assert_eq!(6, scanner.pos());
+        assert!(scanner.check(&colon).is_none());
+        assert!(scanner.check(&frontier).is_some());
+        assert_eq!(6, scanner.pos());
+
+        let line = scanner.scan_until(&line_end).unwrap();
+        assert_eq!(23..25, line.captures[0]);
+        assert_eq!("Accept: */*\r\n", scanner.rest());
+
+        scanner.set_pos(6);
+        assert_eq!(Some(12), scanner.skip(&word));
+        assert!(scanner.check(&frontier).is_none());
+        scanner.set_pos(25);
+        assert_eq!(Some(6), scanner.skip(&word));
+        assert!(scanner.scan_until(&missing).is_none());
+        assert_eq!(31, scanner.pos());
+        assert!(scanner.scan_until(&end).is_some());
+        assert!(scanner.is_end());
+        assert_eq!("", scanner.rest());
+    }
+
+    #[test]
+    fn patterns_built_per_call() {
+        let mut scanner = Scanner::new("a1b22c333");
+        let mut numbers = vec![];
+        while !scanner.is_end() {
+            let letter = compile("%a");
+            scanner.skip(&letter).unwrap();
+            numbers.push(scanner.scan(&compile("%d+")).unwrap().captures[0].len());
+        }
+        assert_eq!(vec![1, 2, 3], numbers);
+    }
+
+    #[test]
+    #[should_panic]
+    fn set_pos_inside_char() {
+        Scanner::new("ж").set_pos(1);
+    }
+}
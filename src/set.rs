@@ -0,0 +1,398 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::{
+    bytecode::{class_table::ClassTable, code::Code},
+    dfa::{closure, expand_chars, loop_closure, looping, MAX_STATES, UNKNOWN},
+    regex::Regex,
+    Match,
+};
+
+/// Many patterns matched against a subject at once.
+///
+/// Patterns the lazy DFA can run (no `%1`, `%b`, `%f`) are joined into one
+/// program and scanned in a single pass; any others are matched one by one.
+#[derive(Debug)]
+pub struct RegexSet {
+    regexes: Box<[Regex]>,
+    combined: Option<Combined>,
+    /// Patterns left out of the combined program.
+    others: Box<[usize]>,
+}
+
+/// Lazy DFA over the programs of several patterns. Works like [`crate::dfa::Dfa`]
+/// in its all-threads mode, but every `Match` tells which pattern it belongs to
+/// and anchors are per pattern.
+#[derive(Debug)]
+struct Combined {
+    classes: Box<[ClassTable]>,
+    class_of: Box<[usize]>,
+    follow: Box<[Box<[usize]>]>,
+    /// Entry threads of every pattern, read at the start of the subject.
+    first: Box<[usize]>,
+    /// Entry threads of patterns not anchored at the start, added at every position.
+    restart: Box<[usize]>,
+    /// Pattern of each `Match` thread, `UNKNOWN` for other threads.
+    pattern_of: Box<[usize]>,
+    anchor_end: Box<[bool]>,
+    ascii_symbols: [usize; 128],
+    ascii_signatures: Box<[Box<[u64]>]>,
+    pool: Mutex<Vec<Cache>>,
+}
+
+#[derive(Debug)]
+struct State {
+    threads: Box<[usize]>,
+    next: Vec<usize>,
+}
+
+#[derive(Debug)]
+struct Cache {
+    states: Vec<State>,
+    map: HashMap<Box<[usize]>, usize>,
+    signatures: Vec<Box<[u64]>>,
+    symbols: HashMap<Box<[u64]>, usize>,
+}
+
+impl RegexSet {
+    pub fn new<I, S>(patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let regexes = patterns
+            .into_iter()
+            .map(|re| Regex::new(re.as_ref()))
+            .collect::<Box<[_]>>();
+        let (joined, others): (Vec<_>, Vec<_>) =
+            (0..regexes.len()).partition(|&n| is_joinable(&regexes[n].program.code));
+        let combined = (!joined.is_empty()).then(|| Combined::new(&regexes, &joined));
+        Self {
+            regexes,
+            combined,
+            others: others.into(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.regexes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regexes.is_empty()
+    }
+
+    /// Compiled pattern number `n`.
+    pub fn regex(&self, n: usize) -> &Regex {
+        &self.regexes[n]
+    }
+
+    pub fn is_match(&self, subj: &str) -> bool {
+        self.combined
+            .as_ref()
+            .is_some_and(|combined| combined.scan(subj, true).contains(&true))
+            || self.others.iter().any(|&n| self.regexes[n].is_match(subj))
+    }
+
+    /// Indices of the patterns which match somewhere in `subj`, in ascending order.
+    pub fn matches(&self, subj: &str) -> Box<[usize]> {
+        let mut matched = match &self.combined {
+            Some(combined) => combined.scan(subj, false),
+            None => vec![false; self.len()],
+        };
+        for &n in self.others.iter() {
+            matched[n] = self.regexes[n].is_match(subj);
+        }
+        (0..self.len()).filter(|&n| matched[n]).collect()
+    }
+
+    /// First match of every pattern, `None` for the patterns which do not match.
+    /// Only the patterns found by [`RegexSet::matches`] are run again for their captures.
+    pub fn first_matches<'a>(&self, subj: &'a str) -> Box<[Option<Match<'a>>]> {
+        let mut firsts = (0..self.len()).map(|_| None).collect::<Box<[_]>>();
+        for n in self.matches(subj).iter() {
+            firsts[*n] = self.regexes[*n].match_one(subj);
+        }
+        firsts
+    }
+}
+
+/// The closure after the last instruction would run into the next pattern,
+/// so a joined program has to end with `Match`.
+fn is_joinable(code: &[Code]) -> bool {
+    code.last() == Some(&Code::Match)
+        && code.iter().all(|code| {
+            matches!(
+                code,
+                Code::Char(_)
+                    | Code::Chars(_)
+                    | Code::MaxExpand { min: 0 | 1, .. }
+                    | Code::MinExpand(_)
+                    | Code::Optional(_)
+                    | Code::Jmp(_)
+                    | Code::Split { .. }
+                    | Code::Save(_)
+                    | Code::Match
+            )
+        })
+}
+
+impl Combined {
+    fn new(regexes: &[Regex], joined: &[usize]) -> Self {
+        let mut program = vec![];
+        let mut tables = vec![];
+        let mut entries = vec![];
+        for &n in joined {
+            let regex = &regexes[n].program;
+            let (code, code_tables) = expand_chars(&regex.code, &regex.tables);
+            let offset = program.len();
+            entries.push((n, offset));
+            program.extend(code.into_iter().map(|code| match code {
+                Code::Jmp(x) => Code::Jmp(x + offset),
+                Code::Split { x, y } => Code::Split {
+                    x: x + offset,
+                    y: y + offset,
+                },
+                code => code,
+            }));
+            tables.extend(code_tables);
+        }
+
+        let mut classes: Vec<ClassTable> = vec![];
+        let mut class_of = vec![UNKNOWN; 2 * program.len()];
+        let mut pattern_of = vec![UNKNOWN; 2 * program.len()];
+        let mut pattern = 0;
+        for (pc, code) in program.iter().enumerate() {
+            while entries
+                .get(pattern + 1)
+                .is_some_and(|&(_, start)| start <= pc)
+            {
+                pattern += 1;
+            }
+            match code {
+                Code::Match => pattern_of[pc] = entries[pattern].0,
+                Code::Jmp(_) | Code::Split { .. } | Code::Save(_) => {}
+                _ => {
+                    let table = &tables[pc];
+                    let class = classes.iter().position(|x| x == table).unwrap_or_else(|| {
+                        classes.push(table.clone());
+                        classes.len() - 1
+                    });
+                    class_of[pc] = class;
+                    class_of[looping(&program, pc)] = class;
+                }
+            }
+        }
+
+        let mut follow = vec![Box::default(); class_of.len()];
+        for (pc, code) in program.iter().enumerate() {
+            match code {
+                Code::Char(_) | Code::Optional(_) => follow[pc] = closure(&program, pc + 1),
+                Code::MaxExpand { .. } | Code::MinExpand(_) => {
+                    follow[pc] = loop_closure(&program, pc);
+                    follow[looping(&program, pc)] = loop_closure(&program, pc);
+                }
+                _ => {}
+            }
+        }
+        let entry_threads = |anchored: bool| {
+            let mut threads = entries
+                .iter()
+                .filter(|(n, _)| anchored || !regexes[*n].program.anchor_start)
+                .flat_map(|&(_, start)| closure(&program, start).into_vec())
+                .collect::<Vec<_>>();
+            threads.sort_unstable();
+            threads.dedup();
+            threads.into_boxed_slice()
+        };
+
+        let mut combined = Self {
+            classes: classes.into(),
+            class_of: class_of.into(),
+            follow: follow.into(),
+            first: entry_threads(true),
+            restart: entry_threads(false),
+            pattern_of: pattern_of.into(),
+            anchor_end: regexes.iter().map(|re| re.program.anchor_end).collect(),
+            ascii_symbols: [0; 128],
+            ascii_signatures: Box::default(),
+            pool: Mutex::new(vec![]),
+        };
+        let mut signatures: Vec<Box<[u64]>> = vec![];
+        for c in 0..128u8 {
+            let signature = combined.signature(c as char);
+            combined.ascii_symbols[c as usize] = signatures
+                .iter()
+                .position(|s| *s == signature)
+                .unwrap_or_else(|| {
+                    signatures.push(signature);
+                    signatures.len() - 1
+                });
+        }
+        combined.ascii_signatures = signatures.into();
+        combined
+    }
+
+    /// Flags of the patterns which match in `subj`, indexed like the whole set.
+    /// With `any` it stops at the first match found.
+    fn scan(&self, subj: &str, any: bool) -> Vec<bool> {
+        let mut matched = vec![false; self.anchor_end.len()];
+        let mut found = 0;
+        let mut record = |threads: &[usize], at_end: bool| {
+            for &thread in threads {
+                let n = self.pattern_of[thread];
+                if n != UNKNOWN && !matched[n] && (at_end || !self.anchor_end[n]) {
+                    matched[n] = true;
+                    found += 1;
+                }
+            }
+            found > 0 && any
+        };
+        let cache = self.pool.lock().unwrap().pop();
+        let mut cache = cache.unwrap_or_else(|| Cache::new(self));
+        let mut state = cache.insert(self.first.clone());
+        let mut chars = subj.chars();
+        loop {
+            let threads = &cache.states[state].threads;
+            let c = chars.next();
+            if record(threads, c.is_none()) {
+                break;
+            }
+            let Some(c) = c else { break };
+            if threads.is_empty() && self.restart.is_empty() {
+                break;
+            }
+            state = cache.next(self, state, c);
+        }
+        self.pool.lock().unwrap().push(cache);
+        matched
+    }
+
+    fn signature(&self, c: char) -> Box<[u64]> {
+        let mut signature = vec![0; self.classes.len().div_ceil(64)];
+        for (n, class) in self.classes.iter().enumerate() {
+            if class.is_matched(c) {
+                signature[n / 64] |= 1 << (n % 64);
+            }
+        }
+        signature.into()
+    }
+}
+
+impl Cache {
+    fn new(combined: &Combined) -> Self {
+        Self {
+            states: vec![],
+            map: HashMap::new(),
+            signatures: combined.ascii_signatures.to_vec(),
+            symbols: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, threads: Box<[usize]>) -> usize {
+        if let Some(&id) = self.map.get(&threads) {
+            return id;
+        }
+        let id = self.states.len();
+        self.map.insert(threads.clone(), id);
+        self.states.push(State {
+            threads,
+            next: vec![],
+        });
+        id
+    }
+
+    fn symbol(&mut self, combined: &Combined, c: char) -> usize {
+        if c.is_ascii() {
+            return combined.ascii_symbols[c as usize];
+        }
+        let signature = combined.signature(c);
+        let signatures = &mut self.signatures;
+        *self.symbols.entry(signature.clone()).or_insert_with(|| {
+            signatures.push(signature);
+            signatures.len() - 1
+        })
+    }
+
+    fn next(&mut self, combined: &Combined, mut state: usize, c: char) -> usize {
+        let symbol = self.symbol(combined, c);
+        if let Some(&next) = self.states[state].next.get(symbol) {
+            if next != UNKNOWN {
+                return next;
+            }
+        }
+        if self.states.len() >= MAX_STATES {
+            let threads = self.states.swap_remove(state).threads;
+            self.states.clear();
+            self.map.clear();
+            state = self.insert(threads);
+        }
+        let signature = &self.signatures[symbol];
+        let mut threads = combined.restart.to_vec();
+        for &pc in self.states[state].threads.iter() {
+            let class = combined.class_of[pc];
+            if class != UNKNOWN && signature[class / 64] >> (class % 64) & 1 == 1 {
+                threads.extend_from_slice(&combined.follow[pc]);
+            }
+        }
+        threads.sort_unstable();
+        threads.dedup();
+        let next = self.insert(threads.into());
+        let transitions = &mut self.states[state].next;
+        if transitions.len() <= symbol {
+            transitions.resize(symbol + 1, UNKNOWN);
+        }
+        transitions[symbol] = next;
+        next
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RegexSet;
+    use crate::regex::compile;
+
+    #[test]
+    fn reports_every_matching_pattern() {
+        let patterns = [
+            "^GET ",
+            "error",
+            "(%d+)%.(%d+)",
+            "%s$",
+            "^%a+$",
+            "%b[]",
+            "(%a)%1",
+            "[^%w%s]",
+            "x*",
+        ];
+        let set = RegexSet::new(patterns);
+        assert_eq!(2, set.others.len());
+        let subjects = [
+            "GET /index error 1.5",
+            "POST [error] ",
+            "letters",
+            "",
+            "book keeper",
+            "пробел ",
+        ];
+        for subj in subjects {
+            let expected = (0..patterns.len())
+                .filter(|&n| compile(patterns[n]).is_match(subj))
+                .collect::<Box<[_]>>();
+            assert_eq!(expected, set.matches(subj), "{:?}", subj);
+            assert_eq!(!expected.is_empty(), set.is_match(subj));
+            for (n, m) in set.first_matches(subj).iter().enumerate() {
+                assert_eq!(
+                    compile(patterns[n]).match_one(subj).map(|m| m.captures),
+                    m.as_ref().map(|m| m.captures.clone())
+                );
+            }
+        }
+        assert!(!RegexSet::new(["a", "b"]).is_match("xyz"));
+        let never = RegexSet::new(["a[]", "a"]);
+        assert!(never.others.is_empty());
+        assert_eq!([1], *never.matches("aa"));
+        assert!(RegexSet::new(Vec::<String>::new())
+            .matches("abc")
+            .is_empty());
+    }
+}
@@ -0,0 +1,312 @@
+use crate::{
+    bytecode::context::{Context, Scratch},
+    regex::Regex,
+    Match,
+};
+
+/// Successive matches the way Lua 5.4's `string.gmatch` finds them: the next
+/// search starts at the end of the previous match, and an empty match is
+/// skipped when it ends where the previous match ended.
+#[derive(Debug)]
+pub(crate) struct Matches<'a> {
+    regex: &'a Regex,
+    subj: &'a str,
+    /// `None` for literal programs, which are searched with `str::find` from byte `from`.
+    ctx: Option<Context<'a>>,
+    from: usize,
+    last_match: Option<usize>,
+}
+
+impl<'a> Matches<'a> {
+    pub(crate) fn new(regex: &'a Regex, subj: &'a str) -> Self {
+        Self::with_scratch(regex, subj, Scratch::default())
+    }
+
+    pub(crate) fn with_scratch(regex: &'a Regex, subj: &'a str, scratch: Scratch) -> Self {
+        Self {
+            regex,
+            subj,
+            ctx: regex
+                .literal()
+                .is_none()
+                .then(|| regex.context(subj, scratch)),
+            from: 0,
+            last_match: None,
+        }
+    }
+
+    pub(crate) fn into_scratch(self) -> Scratch {
+        self.ctx.map(Context::into_scratch).unwrap_or_default()
+    }
+}
+
+impl<'a> Iterator for Matches<'a> {
+    type Item = Match<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some(ctx) = &mut self.ctx else {
+            let found = self
+                .regex
+                .find_literal(self.regex.literal()?, self.subj, self.from)?;
+            self.from = found.end;
+            return Some(Match {
+                subj: self.subj,
+                captures: Box::new([found]),
+                position_captures: self.regex.program.position_captures,
+            });
+        };
+        while ctx.subj_pointer <= ctx.input.len() {
+            let start = ctx.subj_pointer;
+            let found = self.regex.match_here(ctx, start) && self.last_match != Some(ctx.saved[1]);
+            ctx.subj_pointer = if self.regex.program.anchor_start {
+                ctx.input.len() + 1
+            } else if found {
+                ctx.saved[1]
+            } else {
+                start + 1
+            };
+            if found {
+                self.last_match = Some(ctx.saved[1]);
+                let mut ranges = vec![];
+                ctx.read_captured_ranges(&mut ranges);
+                return Some(Match {
+                    subj: self.subj,
+                    captures: ranges.into(),
+                    position_captures: self.regex.program.position_captures,
+                });
+            }
+        }
+        None
+    }
+}
+
+/// Pieces of a subject between matches, see [`Regex::split`].
+#[derive(Debug)]
+pub struct Split<'a> {
+    matches: Matches<'a>,
+    last: usize,
+    finished: bool,
+}
+
+impl<'a> Split<'a> {
+    pub(crate) fn new(regex: &'a Regex, subj: &'a str) -> Self {
+        Self {
+            matches: Matches::new(regex, subj),
+            last: 0,
+            finished: false,
+        }
+    }
+
+    fn remainder(&mut self) -> Option<&'a str> {
+        if self.finished {
+            return None;
+        }
+        self.finished = true;
+        Some(&self.matches.subj[self.last..])
+    }
+}
+
+impl<'a> Iterator for Split<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let Some(m) = self.matches.next() else {
+            return self.remainder();
+        };
+        let piece = &self.matches.subj[self.last..m.captures[0].start];
+        self.last = m.captures[0].end;
+        Some(piece)
+    }
+}
+
+/// At most `n` pieces, the last one being the rest of the subject, see [`Regex::splitn`].
+#[derive(Debug)]
+pub struct SplitN<'a> {
+    split: Split<'a>,
+    n: usize,
+}
+
+impl<'a> SplitN<'a> {
+    pub(crate) fn new(regex: &'a Regex, subj: &'a str, n: usize) -> Self {
+        Self {
+            split: Split::new(regex, subj),
+            n,
+        }
+    }
+}
+
+impl<'a> Iterator for SplitN<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.n {
+            0 => None,
+            1 => {
+                self.n = 0;
+                self.split.remainder()
+            }
+            _ => {
+                self.n -= 1;
+                self.split.next()
+            }
+        }
+    }
+}
+
+/// Pieces of a subject each ending with its separator, see [`Regex::split_inclusive`].
+#[derive(Debug)]
+pub struct SplitInclusive<'a> {
+    split: Split<'a>,
+}
+
+impl<'a> SplitInclusive<'a> {
+    pub(crate) fn new(regex: &'a Regex, subj: &'a str) -> Self {
+        Self {
+            split: Split::new(regex, subj),
+        }
+    }
+}
+
+impl<'a> Iterator for SplitInclusive<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let split = &mut self.split;
+        while !split.finished {
+            let piece = match split.matches.next() {
+                Some(m) => {
+                    let piece = &split.matches.subj[split.last..m.captures[0].end];
+                    split.last = m.captures[0].end;
+                    piece
+                }
+                None => split.remainder()?,
+            };
+            if !piece.is_empty() {
+                return Some(piece);
+            }
+        }
+        None
+    }
+}
+
+/// Item of [`Regex::split_captures`].
+#[derive(Debug)]
+pub enum SplitItem<'a> {
+    /// Text between two separators.
+    Piece(&'a str),
+    /// The match of a separator with its captures.
+    Separator(Match<'a>),
+}
+
+/// Pieces of a subject interleaved with the separators, see [`Regex::split_captures`].
+#[derive(Debug)]
+pub struct SplitCaptures<'a> {
+    split: Split<'a>,
+    separator: Option<Match<'a>>,
+}
+
+impl<'a> SplitCaptures<'a> {
+    pub(crate) fn new(regex: &'a Regex, subj: &'a str) -> Self {
+        Self {
+            split: Split::new(regex, subj),
+            separator: None,
+        }
+    }
+}
+
+impl<'a> Iterator for SplitCaptures<'a> {
+    type Item = SplitItem<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(m) = self.separator.take() {
+            return Some(SplitItem::Separator(m));
+        }
+        let split = &mut self.split;
+        if split.finished {
+            return None;
+        }
+        let Some(m) = split.matches.next() else {
+            return split.remainder().map(SplitItem::Piece);
+        };
+        let piece = &split.matches.subj[split.last..m.captures[0].start];
+        split.last = m.captures[0].end;
+        self.separator = Some(m);
+        Some(SplitItem::Piece(piece))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SplitItem;
+    use crate::regex::compile;
+
+    #[test]
+    fn empty_matches_follow_gmatch() {
+        let comma = compile(",%s*");
+        assert_eq!(
+            vec!["a", "b", "", "c"],
+            comma.split("a, b,,  c").collect::<Vec<_>>()
+        );
+        assert_eq!(vec![""], comma.split("").collect::<Vec<_>>());
+        assert_eq!(
+            vec!["a", "b,,  c"],
+            comma.splitn("a, b,,  c", 2).collect::<Vec<_>>()
+        );
+        assert_eq!(0, comma.splitn("a, b", 0).count());
+        assert_eq!(vec!["a, b"], comma.splitn("a, b", 1).collect::<Vec<_>>());
+        assert_eq!(
+            vec!["c", "", "b", "a"],
+            comma.rsplit("a, b,,  c").collect::<Vec<_>>()
+        );
+
+        // The empty match right after the spaces ends where they end, so it is skipped.
+        let spaces = compile("%s*");
+        assert_eq!(
+            vec!["", "ж", "ы", ""],
+            spaces.split("ж  ы").collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["", "a", "b", ""],
+            compile("x*").split("ab").collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["a", "b"],
+            compile("x*").split_inclusive("ab").collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["one\n", "two\n", "three"],
+            compile("\n")
+                .split_inclusive("one\ntwo\nthree")
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["one\n"],
+            compile("\n").split_inclusive("one\n").collect::<Vec<_>>()
+        );
+        assert_eq!(vec!["", "b"], compile("^a").split("ab").collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn separators_keep_their_captures() {
+        let items = compile("%s*([=;])%s*")
+            .split_captures("x = 1; y")
+            .map(|item| match item {
+                SplitItem::Piece(piece) => format!("piece {}", piece),
+                SplitItem::Separator(m) => format!("separator {}", &m.subj[m.captures[1].clone()]),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "piece x",
+                "separator =",
+                "piece 1",
+                "separator ;",
+                "piece y"
+            ],
+            items
+        );
+    }
+}
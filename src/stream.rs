@@ -0,0 +1,316 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+    ops::Range,
+};
+
+use crate::{
+    bytecode::context::Context,
+    regex::{Regex, Scratch},
+};
+
+const CHUNK: usize = 1 << 16;
+const DEFAULT_MAX_MATCH_LEN: usize = 1 << 16;
+
+/// A match found by [`StreamSearcher`]. Offsets are counted in bytes from the
+/// start of the stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamMatch {
+    pub captures: Box<[Range<usize>]>,
+    /// The whole match.
+    pub text: String,
+}
+
+impl StreamMatch {
+    pub fn capture(&self, n: usize) -> Option<&str> {
+        let start = self.captures[0].start;
+        self.captures
+            .get(n)
+            .map(|r| &self.text[r.start - start..r.end - start])
+    }
+}
+
+/// Finds matches in a stream read in chunks, the way [`Regex::split`] finds them
+/// in a string, without holding more than a chunk and the longest match in memory.
+/// A match which could still change with more input waits for the next chunk,
+/// unless more than `max_match_len` bytes are buffered from its start: then it
+/// is decided on what has been read, so longer matches are cut short or missed.
+#[derive(Debug)]
+pub struct StreamSearcher<'r, R> {
+    regex: &'r Regex,
+    reader: R,
+    max_match_len: usize,
+    /// Decoded text; `buf[0]` is byte `base` of the stream.
+    buf: String,
+    base: usize,
+    /// Bytes read at once.
+    chunk: Box<[u8]>,
+    /// Bytes of a char split by the last read.
+    pending: Vec<u8>,
+    /// Byte in `buf` where the next match is tried.
+    pos: usize,
+    last_match: Option<usize>,
+    ready: VecDeque<StreamMatch>,
+    /// Holds `buf` decoded between scans.
+    scratch: Scratch,
+    eof: bool,
+    done: bool,
+}
+
+impl<'r, R: Read> StreamSearcher<'r, R> {
+    pub fn new(regex: &'r Regex, reader: R) -> Self {
+        Self::with_max_match_len(regex, reader, DEFAULT_MAX_MATCH_LEN)
+    }
+
+    pub fn with_max_match_len(regex: &'r Regex, reader: R, max_match_len: usize) -> Self {
+        Self {
+            regex,
+            reader,
+            max_match_len,
+            buf: String::new(),
+            base: 0,
+            chunk: vec![0; CHUNK].into(),
+            pending: vec![],
+            pos: 0,
+            last_match: None,
+            ready: VecDeque::new(),
+            scratch: Scratch::default(),
+            eof: false,
+            done: false,
+        }
+    }
+
+    /// Tries every start from `pos` on until one cannot be decided without more
+    /// input, queueing the matches found.
+    fn scan(&mut self) {
+        let regex = self.regex;
+        let scratch = std::mem::take(&mut self.scratch);
+        let mut ctx = Context::with_decoded(&[], &[], &self.buf, 0, scratch);
+        regex.attach(&mut ctx);
+        let len = ctx.input.len();
+        let mut start = ctx.input.get_char_index(self.pos).unwrap();
+        while start <= len {
+            let offset = ctx.input.get_byte_index(start).unwrap();
+            if regex.program.anchor_start && self.base + offset > 0 {
+                start = len + 1;
+                break;
+            }
+            ctx.hit_end = false;
+            let mut found = regex.match_here(&mut ctx, start);
+            if !self.eof && (ctx.hit_end || found && regex.program.anchor_end) {
+                if self.buf.len() - offset <= self.max_match_len {
+                    break;
+                }
+                found &= !regex.program.anchor_end;
+            }
+            let end = ctx.saved[1];
+            let absolute_end = found.then(|| self.base + ctx.input.get_byte_index(end).unwrap());
+            if found && self.last_match != absolute_end {
+                let mut ranges = vec![];
+                ctx.read_captured_ranges(&mut ranges);
+                let whole = ranges[0].clone();
+                self.ready.push_back(StreamMatch {
+                    captures: ranges
+                        .into_iter()
+                        .map(|r| self.base + r.start..self.base + r.end)
+                        .collect(),
+                    text: self.buf[whole].to_owned(),
+                });
+                self.last_match = absolute_end;
+                start = end;
+            } else {
+                start += 1;
+            }
+        }
+        // Without more input an empty match at the end may still be followed by others.
+        self.done = start > len && (self.eof || regex.program.anchor_start);
+        self.pos = ctx.input.get_byte_index(start.min(len)).unwrap();
+        self.scratch = ctx.into_scratch();
+    }
+
+    /// Drops the text before `pos` but the char `%f` looks back at, once that is
+    /// at least half of `buf` so the decoded chars are not shifted on every read.
+    /// Then reads a chunk.
+    fn fill(&mut self) -> io::Result<()> {
+        let keep = self.buf[..self.pos]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(i, _)| i);
+        if keep > 0 && 2 * keep >= self.buf.len() {
+            self.buf.drain(..keep);
+            self.scratch.drain_decoded(keep);
+            self.base += keep;
+            self.pos -= keep;
+        }
+
+        let n = loop {
+            match self.reader.read(&mut self.chunk) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                read => break read?,
+            }
+        };
+        if n == 0 {
+            self.eof = true;
+            if !self.pending.is_empty() {
+                return Err(invalid_utf8());
+            }
+            return Ok(());
+        }
+        self.pending.extend_from_slice(&self.chunk[..n]);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => return Err(invalid_utf8()),
+        };
+        let text = std::str::from_utf8(&self.pending[..valid]).unwrap();
+        self.scratch.push_decoded(self.buf.len(), text);
+        self.buf.push_str(text);
+        self.pending.drain(..valid);
+        Ok(())
+    }
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "stream is not valid UTF-8")
+}
+
+impl<R: Read> Iterator for StreamSearcher<'_, R> {
+    type Item = io::Result<StreamMatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(m) = self.ready.pop_front() {
+                return Some(Ok(m));
+            }
+            if self.done {
+                return None;
+            }
+            if self.eof || !self.buf.is_empty() {
+                self.scan();
+                if self.done || !self.ready.is_empty() {
+                    continue;
+                }
+            }
+            if let Err(e) = self.fill() {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{self, Read};
+
+    use super::StreamSearcher;
+    use crate::{regex::compile, split::Matches};
+
+    /// Hands out `step` bytes per read so chars and matches straddle reads.
+    struct Trickle<'a> {
+        bytes: &'a [u8],
+        step: usize,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.step.min(buf.len()).min(self.bytes.len());
+            buf[..n].copy_from_slice(&self.bytes[..n]);
+            self.bytes = &self.bytes[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn same_matches_as_in_a_string() {
+        let subj = "ж1 22 ж333\nабв (4 (5)) x=x;\n";
+        for pattern in [
+            "%d+",
+            "(%a)(%d*)",
+            "%d*",
+            "%f[%w]%w+",
+            "%b()",
+            "(%a)=%1",
+            "^%S+",
+            "%s$",
+            "ж.-\n",
+            "a-",
+            "()",
+        ] {
+            let re = compile(pattern);
+            let expected = Matches::new(&re, subj)
+                .map(|m| m.captures.to_vec())
+                .collect::<Vec<_>>();
+            for step in [1, 2, 5, 100] {
+                let reader = Trickle {
+                    bytes: subj.as_bytes(),
+                    step,
+                };
+                let found = StreamSearcher::new(&re, reader)
+                    .map(|m| m.unwrap().captures.to_vec())
+                    .collect::<Vec<_>>();
+                assert_eq!(expected, found, "{:?} read by {}", pattern, step);
+            }
+        }
+    }
+
+    #[test]
+    fn long_stream_keeps_offsets() {
+        let subj = "ж1 22 ж333\nабв (4 (5)) x=x;\n".repeat(200);
+        let re = compile("%f[%w]%w+");
+        let expected = Matches::new(&re, &subj)
+            .map(|m| m.captures.to_vec())
+            .collect::<Vec<_>>();
+        let reader = Trickle {
+            bytes: subj.as_bytes(),
+            step: 7,
+        };
+        let found = StreamSearcher::with_max_match_len(&re, reader, 16)
+            .map(|m| m.unwrap().captures.to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(expected, found);
+    }
+
+    #[test]
+    fn runs_past_a_chunk() {
+        let subj = "ab ".repeat(40000);
+        for pattern in ["a-", "()", "b%s"] {
+            let re = compile(pattern);
+            let expected = Matches::new(&re, &subj).count();
+            let found = StreamSearcher::new(&re, subj.as_bytes())
+                .map(Result::unwrap)
+                .count();
+            assert_eq!(expected, found, "{:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn bounded_and_broken_input() {
+        let re = compile("a+(b?)");
+        let reader = Trickle {
+            bytes: b"aaaaaab",
+            step: 1,
+        };
+        let found = StreamSearcher::with_max_match_len(&re, reader, 3)
+            .map(|m| m.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec!["aaaa", "aab"],
+            found.iter().map(|m| &*m.text).collect::<Vec<_>>()
+        );
+        assert_eq!(4..7, found[1].captures[0]);
+        assert_eq!(Some("b"), found[1].capture(1));
+
+        let mut bad = StreamSearcher::new(&re, &b"ab\xffa"[..]);
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            bad.next().unwrap().unwrap_err().kind()
+        );
+        assert!(bad.next().is_none());
+        let mut cut = StreamSearcher::new(&re, &"aж".as_bytes()[..2]);
+        assert_eq!(
+            io::ErrorKind::InvalidData,
+            cut.next().unwrap().unwrap_err().kind()
+        );
+    }
+}
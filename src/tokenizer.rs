@@ -0,0 +1,198 @@
+use std::ops::Range;
+
+use crate::{
+    bytecode::context::{Context, Scratch},
+    error::TokenizeError,
+    regex::Regex,
+    Match,
+};
+
+/// Which rule wins when several match at the same position.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// The first rule in order, like a chain of `string.find(s, "^pattern", pos)`.
+    #[default]
+    FirstMatch,
+    /// The longest match, the earlier rule on a tie.
+    LongestMatch,
+}
+
+/// Splits a subject into tokens with an ordered list of `(kind, pattern)` rules.
+/// Every rule is matched anchored at the current position; a rule matching the
+/// empty string there is treated as not matching.
+#[derive(Debug, Clone)]
+pub struct Tokenizer<K> {
+    rules: Box<[(K, Regex)]>,
+    policy: Policy,
+}
+
+#[derive(Debug)]
+pub struct Token<'a, K> {
+    pub kind: K,
+    pub span: Range<usize>,
+    pub matched: Match<'a>,
+}
+
+/// Iterator over the tokens of one subject. Stops after the first error.
+/// The subject is decoded once and every rule runs in the same [`Context`].
+#[derive(Debug)]
+pub struct Tokens<'a, K> {
+    tokenizer: &'a Tokenizer<K>,
+    ctx: Context<'a>,
+    /// Captures of the best match so far, kept as later rules overwrite `ctx`.
+    ranges: Vec<Range<usize>>,
+    position: usize,
+    failed: bool,
+}
+
+impl<K: Clone> Tokenizer<K> {
+    pub fn new<I, S>(rules: I) -> Self
+    where
+        I: IntoIterator<Item = (K, S)>,
+        S: AsRef<str>,
+    {
+        Self::with_policy(rules, Policy::default())
+    }
+
+    pub fn with_policy<I, S>(rules: I, policy: Policy) -> Self
+    where
+        I: IntoIterator<Item = (K, S)>,
+        S: AsRef<str>,
+    {
+        let rules = rules
+            .into_iter()
+            .map(|(kind, re)| (kind, Regex::new(re.as_ref())))
+            .collect();
+        Self { rules, policy }
+    }
+
+    pub fn tokenize<'a>(&'a self, subj: &'a str) -> Tokens<'a, K> {
+        Tokens {
+            tokenizer: self,
+            ctx: Context::with_scratch(&[], &[], subj, 0, Scratch::default()),
+            ranges: vec![],
+            position: 0,
+            failed: false,
+        }
+    }
+}
+
+impl<'a, K: Clone> Iterator for Tokens<'a, K> {
+    type Item = Result<Token<'a, K>, TokenizeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.position >= self.ctx.input.len() {
+            return None;
+        }
+        let mut best: Option<(usize, usize)> = None;
+        for (n, (_, regex)) in self.tokenizer.rules.iter().enumerate() {
+            regex.attach(&mut self.ctx);
+            if !regex.match_here(&mut self.ctx, self.position) || self.ctx.saved[1] == self.position
+            {
+                continue;
+            }
+            let end = self.ctx.saved[1];
+            if best.is_none_or(|(_, longest)| end > longest) {
+                best = Some((n, end));
+                self.ctx.read_captured_ranges(&mut self.ranges);
+                if self.tokenizer.policy == Policy::FirstMatch {
+                    break;
+                }
+            }
+        }
+        let Some((n, end)) = best else {
+            self.failed = true;
+            let offset = self.ctx.input.get_byte_index(self.position).unwrap();
+            return Some(Err(TokenizeError { offset }));
+        };
+        self.position = end;
+        Some(Ok(Token {
+            kind: self.tokenizer.rules[n].0.clone(),
+            span: self.ranges[0].clone(),
+            matched: Match {
+                subj: self.ctx.input.subj,
+                captures: self.ranges.as_slice().into(),
+                position_captures: self.tokenizer.rules[n].1.program.position_captures,
+            },
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Policy, Tokenizer};
+    use crate::error::TokenizeError;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    enum Kind {
+        Name,
+        Keyword,
+        Number,
+        Op,
+        Space,
+        String,
+    }
+
+    #[test]
+    fn first_and_longest_match() {
+        let rules = [
+            (Kind::Keyword, "%f[%w]local%f[%W]"),
+            (Kind::Op, "[=<>~]=?"),
+            (Kind::Name, "[%a_][%w_]*"),
+            (Kind::Number, "%d+%.?%d*"),
+            (Kind::String, "\"([^\"]*)\""),
+            (Kind::Space, "%s+"),
+        ];
+        let lexer = Tokenizer::new(rules);
+        let subj = "local localé = \"ж\" <= 4.5";
+        let tokens = lexer
+            .tokenize(subj)
+            .map(|token| token.map(|t| (t.kind, &subj[t.span])))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            vec![
+                (Kind::Keyword, "local"),
+                (Kind::Space, " "),
+                (Kind::Name, "localé"),
+                (Kind::Space, " "),
+                (Kind::Op, "="),
+                (Kind::Space, " "),
+                (Kind::String, "\"ж\""),
+                (Kind::Space, " "),
+                (Kind::Op, "<="),
+                (Kind::Space, " "),
+                (Kind::Number, "4.5"),
+            ],
+            tokens
+        );
+        let string = lexer.tokenize("\"hi\"").next().unwrap().unwrap();
+        assert_eq!(Some(1..3), string.matched.captures.get(1).cloned());
+
+        let first = Tokenizer::new([(Kind::Op, "<"), (Kind::Op, "<=")]);
+        let longest =
+            Tokenizer::with_policy([(Kind::Op, "<"), (Kind::Op, "<=")], Policy::LongestMatch);
+        assert_eq!(0..1, first.tokenize("<=").next().unwrap().unwrap().span);
+        assert_eq!(0..2, longest.tokenize("<=").next().unwrap().unwrap().span);
+    }
+
+    #[test]
+    fn reports_offset_of_bad_input() {
+        let lexer = Tokenizer::new([(Kind::Name, "%a*"), (Kind::Space, " ")]);
+        let mut tokens = lexer.tokenize("ab ж?");
+        assert_eq!(0..2, tokens.next().unwrap().unwrap().span);
+        assert_eq!(2..3, tokens.next().unwrap().unwrap().span);
+        assert_eq!(3..5, tokens.next().unwrap().unwrap().span);
+        assert_eq!(
+            Some(Err(TokenizeError { offset: 5 })),
+            tokens.next().map(|t| t.map(|t| t.span))
+        );
+        assert!(tokens.next().is_none());
+        assert!(lexer.tokenize("").next().is_none());
+        let no_rules = Tokenizer::<Kind>::new(Vec::<(Kind, &str)>::new());
+        assert_eq!(
+            Some(TokenizeError { offset: 0 }),
+            no_rules.tokenize("a").next().unwrap().err()
+        );
+    }
+}